@@ -0,0 +1,531 @@
+//! Values too large for a single entry
+//!
+//! Values that do not fit in a page are split into chunk entries, each
+//! holding the key and part of the value prefixed with its offset within the
+//! value, followed by a blob entry holding the value length. Chunks are
+//! written and activated first, then activating the blob entry commits the
+//! value, so a partially written value is never visible. Recovery invalidates
+//! chunks without a live blob entry sharing their index.
+//!
+//! Collection may reorder chunks, so chunks are located by offset when read.
+
+use core::fmt::Debug;
+
+use log::{debug, warn};
+
+use crate::header::{align, crc_update, data_crc, EntryFlags, EntryHeader, EntryKind};
+use crate::{parts_len, AsyncFlash, Cursor, Error, KeyIndex, Space, Store};
+
+/// Length of the offset preceding the data in each chunk, and of the value
+/// length held by blob entries
+pub(crate) const OFFSET_LEN: usize = 4;
+
+/// Progress of a value being written in chunks
+#[derive(Clone, Debug)]
+pub(crate) struct Blob {
+    /// Index shared by the chunks and blob entry
+    index: u16,
+    /// Length of the value
+    len: usize,
+    /// Length of the value written so far
+    written: usize,
+    /// Whether the value supersedes an existing entry for the key
+    supersedes: bool,
+}
+
+impl Blob {
+    /// Fetch the length of the value remaining to be written
+    pub(crate) fn remaining(&self) -> usize {
+        self.len - self.written
+    }
+}
+
+impl<F, E, I> Store<F, I>
+where
+    F: AsyncFlash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    /// Write a value in chunks followed by a blob entry, superseding the
+    /// existing entry for the key
+    pub(crate) async fn write_blob(
+        &mut self,
+        key: &[&[u8]],
+        value: &[u8],
+        existing: Option<(usize, EntryHeader)>,
+    ) -> Result<(), Error<E>> {
+        // Check values do not already match, skipping the write if so
+        if let Some((addr, h)) = &existing {
+            if h.is_blob()
+                && value.len() <= u32::MAX as usize
+                && self.blob_matches(*addr, h, key, value).await?
+            {
+                debug!("FKVS skipping write, value unchanged");
+                return Ok(());
+            }
+        }
+
+        let mut blob = self
+            .begin_blob(key, value.len(), value.len(), existing.map(|(_, h)| h))
+            .await?;
+        self.write_chunks(key, &mut blob, value).await?;
+        self.commit_blob(key, blob).await
+    }
+
+    /// Start writing a value in chunks, written in pieces of at most `piece`
+    /// bytes, reserving pages for every chunk
+    ///
+    /// Pages are reserved up front as collecting while the value is
+    /// uncommitted would drop the chunks already written, so no other entries
+    /// may be written until the value is committed.
+    pub(crate) async fn begin_blob(
+        &mut self,
+        key: &[&[u8]],
+        len: usize,
+        piece: usize,
+        existing: Option<EntryHeader>,
+    ) -> Result<Blob, Error<E>> {
+        let key_len = parts_len(key);
+        if key_len > Self::MAX_KEY_LEN {
+            return Err(Error::KeyTooLong);
+        }
+        if len > u32::MAX as usize {
+            return Err(Error::Full);
+        }
+        if piece == 0 {
+            return Err(Error::BufferTooSmall);
+        }
+
+        let index = match &existing {
+            Some(h) => h.index.wrapping_add(1),
+            None => 0,
+        };
+
+        // Chunks left by a value that was never committed would be taken as
+        // part of this value, so are invalidated first
+        let mut c = Cursor::default();
+        while let Some((a, e)) = self.next(&mut c).await? {
+            if e.is_chunk()
+                && e.is_live()
+                && e.index == index
+                && e.key_len as usize == key_len
+                && self.data_matches(a + Self::ENTRY_HEADER_LEN, key).await?
+            {
+                self.clear_entry_flag(a, EntryFlags::VALID).await?;
+            }
+        }
+
+        self.make_space(Space::Chunks {
+            key_len,
+            len,
+            piece,
+        })
+        .await?;
+
+        Ok(Blob {
+            index,
+            len,
+            written: 0,
+            supersedes: existing.is_some(),
+        })
+    }
+
+    /// Write the next piece of a value in chunks, opening pages as they fill
+    pub(crate) async fn write_chunks(
+        &mut self,
+        key: &[&[u8]],
+        blob: &mut Blob,
+        mut data: &[u8],
+    ) -> Result<(), Error<E>> {
+        if data.len() > blob.remaining() {
+            return Err(Error::LengthMismatch);
+        }
+
+        let key_len = parts_len(key);
+        while !data.is_empty() {
+            let n = match Self::chunk_fits(self.page_offset as usize, key_len, data.len()) {
+                Some(n) => n,
+                None => {
+                    self.open_page().await?;
+                    continue;
+                }
+            };
+
+            let (d, rest) = data.split_at(n);
+            let o = (blob.written as u32).to_le_bytes();
+            let h = EntryHeader {
+                index: blob.index,
+                kind: EntryKind::Chunk,
+                flags: EntryFlags::DEFAULT,
+                key_len: key_len as u16,
+                val_len: (OFFSET_LEN + n) as u16,
+                crc: crc_update(data_crc(key, &o), d),
+            };
+
+            self.append(h, key, [&o[..], d]).await?;
+            blob.written += n;
+            data = rest;
+        }
+
+        Ok(())
+    }
+
+    /// Commit a value once every chunk has been written, activating its blob
+    /// entry then invalidating the previous entry for the key
+    pub(crate) async fn commit_blob(&mut self, key: &[&[u8]], blob: Blob) -> Result<(), Error<E>> {
+        if blob.remaining() != 0 {
+            return Err(Error::LengthMismatch);
+        }
+
+        let key_len = parts_len(key);
+        let len = (blob.len as u32).to_le_bytes();
+        let h = EntryHeader {
+            index: blob.index,
+            kind: EntryKind::Blob,
+            flags: EntryFlags::DEFAULT,
+            key_len: key_len as u16,
+            val_len: OFFSET_LEN as u16,
+            crc: data_crc(key, &len),
+        };
+        if self.page_offset as usize + h.entry_len(F::WRITE_SIZE) > F::PAGE_SIZE {
+            self.open_page().await?;
+        }
+        let addr = self.append(h.clone(), key, [&len[..], &[]]).await?;
+
+        // Invalidate the previous entry along with any chunks
+        if blob.supersedes {
+            self.invalidate_superseded(addr, &h).await?;
+        }
+
+        self.index_entry(addr, &h).await
+    }
+
+    /// Write and activate an entry at the active page offset, returning the
+    /// entry address
+    ///
+    /// Space for the entry must already be available in the active page
+    async fn append(
+        &mut self,
+        h: EntryHeader,
+        key: &[&[u8]],
+        value: [&[u8]; 2],
+    ) -> Result<usize, Error<E>> {
+        let len = h.entry_len(F::WRITE_SIZE);
+        let addr = self.page_addr(self.page_active as usize) + self.page_offset as usize;
+
+        self.set_entry_header(addr, h).await?;
+        self.write_data(
+            addr + Self::ENTRY_HEADER_LEN,
+            key.iter().copied().chain(value),
+        )
+        .await?;
+        self.page_offset += len as u32;
+
+        self.clear_entry_flag(addr, EntryFlags::INACTIVE).await?;
+
+        Ok(addr)
+    }
+
+    /// Compute the length of value data fitting in a chunk written at a page
+    /// offset, returning None if no data fits
+    fn chunk_fits(offset: usize, key_len: usize, remaining: usize) -> Option<usize> {
+        match F::PAGE_SIZE.checked_sub(offset + Self::ENTRY_HEADER_LEN + key_len + OFFSET_LEN) {
+            Some(0) | None => None,
+            Some(n) => Some(usize::min(
+                usize::min(n, u16::MAX as usize - OFFSET_LEN),
+                remaining,
+            )),
+        }
+    }
+
+    /// Lay out a value written in pieces of at most `piece` bytes in chunks
+    /// from the active page offset, returning the number of pages to open and
+    /// the total length of the entries, or None if the key leaves no space for
+    /// data in a page
+    pub(crate) fn chunk_layout(
+        &self,
+        key_len: usize,
+        len: usize,
+        piece: usize,
+    ) -> Option<(usize, usize)> {
+        let (mut offset, mut pages, mut used) = (self.page_offset as usize, 0, 0);
+
+        // Pieces are split into chunks at page boundaries
+        let (mut done, mut end) = (0, 0);
+        while done < len {
+            if done == end {
+                end = usize::min(done + piece, len);
+            }
+
+            match Self::chunk_fits(offset, key_len, end - done) {
+                Some(n) => {
+                    let n_len =
+                        Self::ENTRY_HEADER_LEN + align(key_len + OFFSET_LEN + n, F::WRITE_SIZE);
+                    offset += n_len;
+                    used += n_len;
+                    done += n;
+                }
+                None if offset == Self::PAGE_HEADER_LEN => return None,
+                None => {
+                    offset = Self::PAGE_HEADER_LEN;
+                    pages += 1;
+                }
+            }
+        }
+
+        // Followed by the blob entry
+        let blob_len = Self::ENTRY_HEADER_LEN + align(key_len + OFFSET_LEN, F::WRITE_SIZE);
+        if offset + blob_len > F::PAGE_SIZE {
+            pages += 1;
+        }
+
+        Some((pages, used + blob_len))
+    }
+
+    /// Read a value stored in chunks, checking the CRC of each chunk
+    pub(crate) async fn read_blob(
+        &mut self,
+        addr: usize,
+        h: &EntryHeader,
+        value: &mut [u8],
+    ) -> Result<usize, Error<E>> {
+        let len = self.value_len(addr, h).await?;
+        if value.len() < len {
+            return Err(Error::BufferTooSmall);
+        }
+
+        let mut offset = 0;
+        while offset < len {
+            let (a, c, start) = match self.find_chunk(addr, h, offset).await? {
+                Some(c) => c,
+                None => {
+                    warn!(
+                        "FKVS missing chunk at offset {} for entry at 0x{:08x}",
+                        offset, addr
+                    );
+                    return Err(Error::Corrupt);
+                }
+            };
+
+            let n = c.val_len as usize - OFFSET_LEN;
+            if start + n > len {
+                return Err(Error::Corrupt);
+            }
+
+            let data = &mut value[start..start + n];
+            self.flash
+                .read(
+                    a + Self::ENTRY_HEADER_LEN + c.key_len as usize + OFFSET_LEN,
+                    data,
+                )
+                .await?;
+
+            let crc = crc_update(self.key_crc(a, &c).await?, &(start as u32).to_le_bytes());
+            if crc_update(crc, data) != c.crc {
+                warn!("FKVS data CRC mismatch for chunk at 0x{:08x}", a);
+                return Err(Error::Corrupt);
+            }
+
+            offset = start + n;
+        }
+
+        Ok(len)
+    }
+
+    /// Read part of a value stored in chunks starting at a byte offset,
+    /// checking the CRC of each chunk read from
+    pub(crate) async fn read_blob_at(
+        &mut self,
+        addr: usize,
+        h: &EntryHeader,
+        offset: usize,
+        value: &mut [u8],
+    ) -> Result<usize, Error<E>> {
+        let len = self.value_len(addr, h).await?;
+        let end = offset + usize::min(value.len(), len.saturating_sub(offset));
+
+        let mut pos = offset;
+        while pos < end {
+            let (a, c, start) = match self.find_chunk(addr, h, pos).await? {
+                Some(c) => c,
+                None => {
+                    warn!(
+                        "FKVS missing chunk at offset {} for entry at 0x{:08x}",
+                        pos, addr
+                    );
+                    return Err(Error::Corrupt);
+                }
+            };
+
+            // Read the overlap of the chunk with the requested part
+            let n = usize::min(start + c.val_len as usize - OFFSET_LEN, end) - pos;
+            self.read_part(
+                a,
+                &c,
+                OFFSET_LEN + pos - start,
+                &mut value[pos - offset..][..n],
+            )
+            .await?;
+            pos += n;
+        }
+
+        Ok(end - offset)
+    }
+
+    /// Fetch the length of the value of an entry, reading the length of
+    /// values stored in chunks from the blob entry
+    pub(crate) async fn value_len(
+        &mut self,
+        addr: usize,
+        h: &EntryHeader,
+    ) -> Result<usize, Error<E>> {
+        if !h.is_blob() {
+            return Ok(h.val_len as usize);
+        }
+
+        let mut len = [0u8; OFFSET_LEN];
+        self.flash
+            .read(addr + Self::ENTRY_HEADER_LEN + h.key_len as usize, &mut len)
+            .await?;
+
+        if h.val_len as usize != OFFSET_LEN
+            || crc_update(self.key_crc(addr, h).await?, &len) != h.crc
+        {
+            warn!("FKVS data CRC mismatch for entry at 0x{:08x}", addr);
+            return Err(Error::Corrupt);
+        }
+
+        Ok(u32::from_le_bytes(len) as usize)
+    }
+
+    /// Check whether a value stored in chunks matches the provided value
+    async fn blob_matches(
+        &mut self,
+        addr: usize,
+        h: &EntryHeader,
+        key: &[&[u8]],
+        value: &[u8],
+    ) -> Result<bool, Error<E>> {
+        let key_len = parts_len(key);
+        let len = (value.len() as u32).to_le_bytes();
+        if h.crc != data_crc(key, &len)
+            || !self
+                .data_matches(addr + Self::ENTRY_HEADER_LEN + key_len, &[&len])
+                .await?
+        {
+            return Ok(false);
+        }
+
+        let mut offset = 0;
+        while offset < value.len() {
+            let (a, c, start) = match self.find_chunk(addr, h, offset).await? {
+                Some(c) => c,
+                None => return Ok(false),
+            };
+
+            let n = c.val_len as usize - OFFSET_LEN;
+            if start + n > value.len()
+                || !self
+                    .data_matches(
+                        a + Self::ENTRY_HEADER_LEN + key_len + OFFSET_LEN,
+                        &[&value[start..start + n]],
+                    )
+                    .await?
+            {
+                return Ok(false);
+            }
+
+            offset = start + n;
+        }
+
+        Ok(true)
+    }
+
+    /// Locate the live chunk of a blob entry holding the byte at an offset
+    /// within the value, returning the chunk address and header and the
+    /// offset of the chunk
+    async fn find_chunk(
+        &mut self,
+        addr: usize,
+        h: &EntryHeader,
+        offset: usize,
+    ) -> Result<Option<(usize, EntryHeader, usize)>, Error<E>> {
+        let mut c = Cursor::default();
+
+        while let Some((a, e)) = self.next(&mut c).await? {
+            if !e.is_chunk() || !e.is_live() || e.index != h.index || e.key_len != h.key_len {
+                continue;
+            }
+
+            let start = self.chunk_offset(a, &e).await?;
+            let n = (e.val_len as usize).saturating_sub(OFFSET_LEN);
+            if (start..start + n).contains(&offset)
+                && self
+                    .flash_matches(
+                        a + Self::ENTRY_HEADER_LEN,
+                        addr + Self::ENTRY_HEADER_LEN,
+                        h.key_len as usize,
+                    )
+                    .await?
+            {
+                return Ok(Some((a, e, start)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Check whether a live chunk belongs to the latest value for its key,
+    /// and has not been copied to a later entry by collection
+    pub(crate) async fn is_latest_chunk(
+        &mut self,
+        addr: usize,
+        h: &EntryHeader,
+    ) -> Result<bool, Error<E>> {
+        let offset = self.chunk_offset(addr, h).await?;
+        let mut c = Cursor::default();
+        let (mut after, mut blob) = (false, false);
+
+        while let Some((a, e)) = self.next(&mut c).await? {
+            if a == addr {
+                after = true;
+                continue;
+            }
+
+            if !e.is_live() || e.index != h.index || e.key_len != h.key_len {
+                continue;
+            }
+
+            let copy = after && e.is_chunk() && self.chunk_offset(a, &e).await? == offset;
+            if (e.is_blob() || copy)
+                && self
+                    .flash_matches(
+                        a + Self::ENTRY_HEADER_LEN,
+                        addr + Self::ENTRY_HEADER_LEN,
+                        h.key_len as usize,
+                    )
+                    .await?
+            {
+                if copy {
+                    return Ok(false);
+                }
+                blob = true;
+            }
+        }
+
+        Ok(blob)
+    }
+
+    /// Read the offset of a chunk within its value
+    async fn chunk_offset(&mut self, addr: usize, h: &EntryHeader) -> Result<usize, Error<E>> {
+        let mut offset = [0u8; OFFSET_LEN];
+        self.flash
+            .read(
+                addr + Self::ENTRY_HEADER_LEN + h.key_len as usize,
+                &mut offset,
+            )
+            .await?;
+
+        Ok(u32::from_le_bytes(offset) as usize)
+    }
+}
@@ -0,0 +1,383 @@
+//! On-flash page and entry header encoding
+//!
+//! All fields are little-endian and protected by a header CRC. Flags are
+//! stored after the header fields, each in a separate word of the flash write
+//! size, so each may be cleared exactly once as the page or entry changes
+//! state without re-programming any other data.
+
+use bitflags::bitflags;
+use crc::crc32;
+
+use crate::Error;
+
+/// Maximum encoded header length including flag words
+pub(crate) const MAX_HEADER_LEN: usize = 96;
+
+/// Result of decoding a header read from flash
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Header<T> {
+    /// Header slot is erased (all bytes 0xFF)
+    Erased,
+    /// Header decoded with a valid CRC
+    Valid(T),
+    /// Header has been written but failed to decode
+    Corrupt,
+}
+
+impl<T> Header<T> {
+    /// Fetch a valid header, returning an error for erased or corrupt headers
+    pub fn valid<E>(self) -> Result<T, Error<E>> {
+        match self {
+            Header::Valid(h) => Ok(h),
+            _ => Err(Error::Corrupt),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(u8)]
+pub enum PageKind {
+    /// Standard K:V data page
+    Standard = 0x00,
+    /// Marks a format in progress, so the store is wiped when next mounted
+    Reset = 0x01,
+}
+
+bitflags!(
+  pub(crate) struct PageFlags: u16 {
+    /// Default to all bits set for FLASH erased
+    const DEFAULT = 0xFFFF;
+
+    /// Indicates a page is not in use (clear to activeate)
+    const INACTIVE = (1 << 0);
+
+    /// Indicates a page is valid (clear to invalidate)
+    const VALID = (1 << 1);
+  }
+);
+
+/// PageHeader identifies a flash pages in the NVS
+///
+/// ```text
+/// 0     1      2       6        10    14          +W          +W
+/// | ver | kind | index | erases | crc | pad to W | INACTIVE | VALID |
+/// ```
+///
+/// Pages are erased only to write a new header, which records the number of
+/// times the page has been erased.
+///
+/// Version 3 headers lack the erase count, with the CRC at offset 6. Later
+/// versions must keep the version and CRC at the offsets of version 4, so
+/// that stores written by newer versions are detected rather than taken as
+/// corrupt.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PageHeader {
+    /// File system version ID, see [`PageHeader::VERSION`]
+    pub version: u8,
+    /// Page kind, specifies how the page should be read
+    pub kind: PageKind,
+    /// Page index, wrapping monotonic count
+    pub index: u32,
+    /// Number of times the page has been erased
+    pub erases: u32,
+    /// Page usage flags
+    pub flags: PageFlags,
+}
+
+impl PageHeader {
+    /// Current file system version
+    pub const VERSION: u8 = 6;
+
+    /// Encoded length of the header fields, excluding flags
+    pub const LEN: usize = 14;
+
+    /// Encoded length of the header fields for a format version
+    const fn fields_len(version: u8) -> usize {
+        match version {
+            3 => 10,
+            _ => Self::LEN,
+        }
+    }
+
+    /// Flags stored in separate words following the header fields
+    const FLAGS: [PageFlags; 2] = [PageFlags::INACTIVE, PageFlags::VALID];
+
+    /// Length of the header including flags for the provided write size,
+    /// and the offset of the first entry in a page
+    pub const fn len(write_size: usize) -> usize {
+        align(Self::LEN, write_size) + Self::FLAGS.len() * write_size
+    }
+
+    /// Offset of the word storing a flag from the start of a header with the
+    /// provided format version
+    pub fn flag_offset(version: u8, flag: PageFlags, write_size: usize) -> usize {
+        align(Self::fields_len(version), write_size)
+            + flag.bits().trailing_zeros() as usize * write_size
+    }
+
+    /// Offset of the first entry in the page, which depends on the version
+    /// of the page header
+    pub fn entries_offset(&self, write_size: usize) -> usize {
+        align(Self::fields_len(self.version), write_size) + Self::FLAGS.len() * write_size
+    }
+
+    /// Encode page header fields, computing the header CRC
+    pub fn encode(&self, buff: &mut [u8; Self::LEN]) {
+        buff[0] = self.version;
+        buff[1] = self.kind.clone() as u8;
+        buff[2..6].copy_from_slice(&self.index.to_le_bytes());
+        buff[6..10].copy_from_slice(&self.erases.to_le_bytes());
+
+        let crc = crc32::checksum_ieee(&buff[..10]);
+        buff[10..14].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Decode a page header and flags, checking the header CRC
+    pub fn decode(buff: &[u8], write_size: usize) -> Header<Self> {
+        if is_erased(buff) {
+            return Header::Erased;
+        }
+
+        let version = buff[0];
+        let n = Self::fields_len(version) - 4;
+        if crc32::checksum_ieee(&buff[..n])
+            != u32::from_le_bytes([buff[n], buff[n + 1], buff[n + 2], buff[n + 3]])
+        {
+            return Header::Corrupt;
+        }
+
+        let kind = match buff[1] {
+            0x00 => PageKind::Standard,
+            0x01 => PageKind::Reset,
+            _ => return Header::Corrupt,
+        };
+
+        // Flags are cleared once any bit in their word is programmed
+        let mut flags = PageFlags::DEFAULT;
+        for f in Self::FLAGS.iter() {
+            let o = Self::flag_offset(version, *f, write_size);
+            if !is_erased(&buff[o..o + write_size]) {
+                flags.remove(*f);
+            }
+        }
+
+        // Version 3 pages were not counted
+        let erases = match version {
+            3 => 0,
+            _ => u32::from_le_bytes([buff[6], buff[7], buff[8], buff[9]]),
+        };
+
+        Header::Valid(Self {
+            version,
+            kind,
+            index: u32::from_le_bytes([buff[2], buff[3], buff[4], buff[5]]),
+            erases,
+            flags,
+        })
+    }
+
+    /// Check whether a page has been activated and not since invalidated
+    pub fn is_live(&self) -> bool {
+        !self.flags.contains(PageFlags::INACTIVE) && self.flags.contains(PageFlags::VALID)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(u8)]
+pub(crate) enum EntryKind {
+    /// Key and value data
+    Data = 0x00,
+    /// Marks a key as deleted, superseding previous entries
+    Tombstone = 0x01,
+    /// Commits the preceding entries of a transaction
+    Commit = 0x02,
+    /// Part of a value too large for a single entry
+    Chunk = 0x03,
+    /// Key and length of a value stored in chunks
+    Blob = 0x04,
+}
+
+bitflags!(
+  pub(crate) struct EntryFlags: u16 {
+    /// Default to all bits set for FLASH erased
+    const DEFAULT = 0xFFFF;
+
+    /// Indicates an entry is not in use (clear to activate)
+    const INACTIVE = (1 << 0);
+
+    /// Indicates an entry is valid (clear to invalidate)
+    const VALID = (1 << 1);
+  }
+);
+
+/// EntryHeader precedes the key and value of each entry in a page
+///
+/// ```text
+/// 0       2         4         6          10     11           15         +W          +W
+/// | index | key_len | val_len | data crc | kind | header crc | pad to W | INACTIVE | VALID |
+/// ```
+///
+/// The key and value follow the header, padded to the write size. Tombstones
+/// carry only the key, and commit markers only a value holding the page offset
+/// of the first entry in their transaction.
+///
+/// Values too large for a page are split into chunks, each holding the key and
+/// a value prefixed with the u32 offset of the chunk within the value. Chunks
+/// share the index of the blob entry following them, whose value holds the
+/// u32 length of the value.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct EntryHeader {
+    /// Entry index, per-key wrapping monotonic count
+    pub index: u16,
+
+    /// Entry kind, specifies how the entry should be read
+    pub kind: EntryKind,
+
+    pub flags: EntryFlags,
+
+    pub key_len: u16,
+
+    pub val_len: u16,
+
+    /// CRC over the key and value data
+    pub crc: u32,
+}
+
+impl EntryHeader {
+    /// Encoded length of the header fields, excluding flags
+    pub const LEN: usize = 15;
+
+    /// Flags stored in separate words following the header fields
+    const FLAGS: [EntryFlags; 2] = [EntryFlags::INACTIVE, EntryFlags::VALID];
+
+    /// Length of the header including flags for the provided write size,
+    /// and the offset of the key from the start of the entry
+    pub const fn len(write_size: usize) -> usize {
+        align(Self::LEN, write_size) + Self::FLAGS.len() * write_size
+    }
+
+    /// Offset of the word storing a flag from the start of the header
+    pub fn flag_offset(flag: EntryFlags, write_size: usize) -> usize {
+        align(Self::LEN, write_size) + flag.bits().trailing_zeros() as usize * write_size
+    }
+
+    /// Encode entry header fields, computing the header CRC
+    pub fn encode(&self, buff: &mut [u8; Self::LEN]) {
+        buff[0..2].copy_from_slice(&self.index.to_le_bytes());
+        buff[2..4].copy_from_slice(&self.key_len.to_le_bytes());
+        buff[4..6].copy_from_slice(&self.val_len.to_le_bytes());
+        buff[6..10].copy_from_slice(&self.crc.to_le_bytes());
+        buff[10] = self.kind.clone() as u8;
+
+        let crc = crc32::checksum_ieee(&buff[..11]);
+        buff[11..15].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Decode an entry header and flags, checking the header CRC
+    pub fn decode(buff: &[u8], write_size: usize) -> Header<Self> {
+        if is_erased(buff) {
+            return Header::Erased;
+        }
+
+        if crc32::checksum_ieee(&buff[..11])
+            != u32::from_le_bytes([buff[11], buff[12], buff[13], buff[14]])
+        {
+            return Header::Corrupt;
+        }
+
+        let kind = match buff[10] {
+            0x00 => EntryKind::Data,
+            0x01 => EntryKind::Tombstone,
+            0x02 => EntryKind::Commit,
+            0x03 => EntryKind::Chunk,
+            0x04 => EntryKind::Blob,
+            _ => return Header::Corrupt,
+        };
+
+        // Flags are cleared once any bit in their word is programmed
+        let mut flags = EntryFlags::DEFAULT;
+        for f in Self::FLAGS.iter() {
+            let o = Self::flag_offset(*f, write_size);
+            if !is_erased(&buff[o..o + write_size]) {
+                flags.remove(*f);
+            }
+        }
+
+        Header::Valid(Self {
+            index: u16::from_le_bytes([buff[0], buff[1]]),
+            kind,
+            flags,
+            key_len: u16::from_le_bytes([buff[2], buff[3]]),
+            val_len: u16::from_le_bytes([buff[4], buff[5]]),
+            crc: u32::from_le_bytes([buff[6], buff[7], buff[8], buff[9]]),
+        })
+    }
+
+    /// Total length of the entry including the header, key, and value,
+    /// padded to the write size
+    pub fn entry_len(&self, write_size: usize) -> usize {
+        Self::len(write_size) + align(self.key_len as usize + self.val_len as usize, write_size)
+    }
+
+    /// Check whether an entry has been activated and not since invalidated
+    pub fn is_live(&self) -> bool {
+        !self.flags.contains(EntryFlags::INACTIVE) && self.flags.contains(EntryFlags::VALID)
+    }
+
+    /// Check whether an entry marks its key as deleted
+    pub fn is_tombstone(&self) -> bool {
+        self.kind == EntryKind::Tombstone
+    }
+
+    /// Check whether an entry is a transaction commit marker
+    pub fn is_commit(&self) -> bool {
+        self.kind == EntryKind::Commit
+    }
+
+    /// Check whether an entry is part of a value stored in chunks
+    pub fn is_chunk(&self) -> bool {
+        self.kind == EntryKind::Chunk
+    }
+
+    /// Check whether an entry holds the length of a value stored in chunks
+    pub fn is_blob(&self) -> bool {
+        self.kind == EntryKind::Blob
+    }
+
+    /// Check whether an entry records the state of its key, rather than
+    /// being a commit marker or chunk
+    pub fn is_record(&self) -> bool {
+        !self.is_commit() && !self.is_chunk()
+    }
+}
+
+/// Compute the CRC over entry key and value data, with the key provided in
+/// parts
+pub(crate) fn data_crc(key: &[&[u8]], value: &[u8]) -> u32 {
+    crc_update(key.iter().fold(0, |c, k| crc_update(c, k)), value)
+}
+
+/// Update a data CRC with further data
+pub(crate) fn crc_update(crc: u32, data: &[u8]) -> u32 {
+    crc32::update(crc, &crc32::IEEE_TABLE, data)
+}
+
+/// Round a length up to a multiple of the flash write size
+pub(crate) const fn align(len: usize, write_size: usize) -> usize {
+    len.div_ceil(write_size) * write_size
+}
+
+/// Compare wrapping entry indices, returning true if `a` is newer than `b`
+pub(crate) fn index_newer(a: u16, b: u16) -> bool {
+    (a.wrapping_sub(b) as i16) > 0
+}
+
+/// Compare wrapping page indices, returning true if `a` is newer than `b`
+pub(crate) fn page_newer(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Check whether a buffer has been read from erased flash
+fn is_erased(buff: &[u8]) -> bool {
+    buff.iter().all(|b| *b == 0xFF)
+}
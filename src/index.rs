@@ -0,0 +1,162 @@
+//! In-RAM index of key hashes to entry addresses
+//!
+//! Without an index, every read and write walks the store to find the latest
+//! entry for a key. An [`Index`] of `N` slots maps a hash of each key to the
+//! address of its latest entry, so lookups read only the entries whose key
+//! hash matches. The index is built when the store is mounted and updated as
+//! entries are written, deleted and collected.
+//!
+//! The index is provided by the caller, either owned by the store or
+//! borrowed from a buffer elsewhere:
+//!
+//! ```
+//! # #[cfg(feature = "mock")] {
+//! # use fkvs::{Index, Kvs, Options, mock::MockKvs};
+//! # fn setup(flash: MockKvs<2048, 4>, opts: Options) {
+//! let mut index = Index::<64>::new();
+//! let mut kvs = Kvs::with_index(flash, opts, &mut index).unwrap();
+//! # }
+//! # }
+//! ```
+//!
+//! Should the store hold more keys than the index has slots, lookups fall
+//! back to walking the store until the index is next rebuilt.
+
+/// Slot address marking an empty slot
+///
+/// Entries end within the flash address space, so never start at either
+/// sentinel address.
+const EMPTY: usize = usize::MAX;
+
+/// Slot address marking a slot from which an entry was removed
+const REMOVED: usize = usize::MAX - 1;
+
+/// Index slot mapping a key hash to an entry address
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Slot {
+    hash: u32,
+    addr: usize,
+}
+
+impl Slot {
+    const EMPTY: Slot = Slot {
+        hash: 0,
+        addr: EMPTY,
+    };
+}
+
+/// Storage for a key index, see [`Index`]
+pub trait KeyIndex {
+    /// Fetch the index slots
+    fn slots(&mut self) -> &mut [Slot];
+}
+
+/// Key index with `N` slots
+pub struct Index<const N: usize> {
+    slots: [Slot; N],
+}
+
+impl<const N: usize> Index<N> {
+    /// Create an empty index
+    pub const fn new() -> Self {
+        Self {
+            slots: [Slot::EMPTY; N],
+        }
+    }
+}
+
+impl<const N: usize> Default for Index<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> KeyIndex for Index<N> {
+    fn slots(&mut self) -> &mut [Slot] {
+        &mut self.slots
+    }
+}
+
+impl<T: KeyIndex + ?Sized> KeyIndex for &mut T {
+    fn slots(&mut self) -> &mut [Slot] {
+        (**self).slots()
+    }
+}
+
+/// Empty key index, lookups always walk the store
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NoIndex;
+
+impl KeyIndex for NoIndex {
+    fn slots(&mut self) -> &mut [Slot] {
+        &mut []
+    }
+}
+
+/// Clear all slots
+pub(crate) fn clear(slots: &mut [Slot]) {
+    slots.fill(Slot::EMPTY);
+}
+
+/// Store an entry address in the first free slot for a hash, returning false
+/// if no free slot remains
+pub(crate) fn insert(slots: &mut [Slot], hash: u32, addr: usize) -> bool {
+    let n = slots.len();
+    for i in 0..n {
+        let s = &mut slots[(hash as usize + i) % n];
+        if s.addr == EMPTY || s.addr == REMOVED {
+            *s = Slot { hash, addr };
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Replace the address stored in a slot
+pub(crate) fn replace(slots: &mut [Slot], pos: usize, addr: usize) {
+    slots[pos].addr = addr;
+}
+
+/// Remove the slot holding an entry address
+pub(crate) fn remove(slots: &mut [Slot], hash: u32, addr: usize) {
+    let mut p = Probe::new(hash);
+    while let Some((pos, a)) = p.next(slots) {
+        if a == addr {
+            slots[pos].addr = REMOVED;
+            return;
+        }
+    }
+}
+
+/// Walk the slots that may hold a hash, from the slot the hash selects up to
+/// the first empty slot
+pub(crate) struct Probe {
+    hash: u32,
+    step: usize,
+}
+
+impl Probe {
+    pub fn new(hash: u32) -> Self {
+        Self { hash, step: 0 }
+    }
+
+    /// Fetch the position and entry address of the next slot matching the hash
+    pub fn next(&mut self, slots: &[Slot]) -> Option<(usize, usize)> {
+        let n = slots.len();
+        while self.step < n {
+            let pos = (self.hash as usize + self.step) % n;
+            self.step += 1;
+
+            let s = slots[pos];
+            if s.addr == EMPTY {
+                return None;
+            }
+            if s.addr != REMOVED && s.hash == self.hash {
+                return Some((pos, s.addr));
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,188 @@
+//! Iteration over the live entries in a store
+//!
+//! Iterators visit the latest value of each key once, skipping superseded
+//! entries and deleted keys. Iterators over a [`Namespace`](crate::Namespace)
+//! visit only keys within the namespace, with the prefix stripped.
+//!
+//! Entries are visited in the order they were written, walking pages from
+//! oldest to newest by index, with keys and values read from flash only on
+//! request.
+//!
+//! As entries borrow the iterator, iteration uses `while let` rather than
+//! the standard `Iterator` trait:
+//!
+//! ```
+//! # #[cfg(feature = "mock")] {
+//! # use fkvs::{Kvs, Options, mock::MockKvs};
+//! # fn list(kvs: &mut Kvs<MockKvs<2048, 4>>) {
+//! let mut iter = kvs.iter();
+//! while let Some(mut entry) = iter.next().unwrap() {
+//!     let mut key = [0u8; 32];
+//!     let n = entry.read_key(&mut key).unwrap();
+//!     log::info!("{:?}: {} bytes", &key[..n], entry.value_len());
+//! }
+//! # }
+//! # }
+//! ```
+
+use core::fmt::Debug;
+
+use crate::header::EntryHeader;
+use crate::{block_on, AsyncFlash, Blocking, Cursor, Error, Flash, KeyIndex, NoIndex, Store};
+
+/// Iterator over the latest value of each key, created by [`Kvs::iter`](crate::Kvs::iter)
+pub struct Iter<'a, F: Flash, I: KeyIndex = NoIndex> {
+    store: &'a mut Store<Blocking<F>, I>,
+    cursor: Cursor,
+    prefix: &'a [u8],
+}
+
+impl<'a, F, E, I> Iter<'a, F, I>
+where
+    F: Flash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    pub(crate) fn new(store: &'a mut Store<Blocking<F>, I>, prefix: &'a [u8]) -> Self {
+        Self {
+            store,
+            cursor: Cursor::default(),
+            prefix,
+        }
+    }
+
+    /// Fetch the next entry, returning None once all entries have been visited
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<Entry<'_, F, I>>, Error<E>> {
+        let (addr, header) = match block_on(self.store.next_live(&mut self.cursor, self.prefix))? {
+            Some(e) => e,
+            None => return Ok(None),
+        };
+        let len = block_on(self.store.value_len(addr, &header))?;
+
+        Ok(Some(Entry {
+            store: self.store,
+            addr,
+            header,
+            len,
+            skip: self.prefix.len(),
+        }))
+    }
+}
+
+/// Entry visited by an [`Iter`]
+pub struct Entry<'a, F: Flash, I: KeyIndex = NoIndex> {
+    store: &'a mut Store<Blocking<F>, I>,
+    addr: usize,
+    header: EntryHeader,
+    /// Length of the value, which may be stored in chunks
+    len: usize,
+    /// Length of the key prefix to strip
+    skip: usize,
+}
+
+impl<'a, F, E, I> Entry<'a, F, I>
+where
+    F: Flash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    /// Fetch the length of the entry key
+    pub fn key_len(&self) -> usize {
+        self.header.key_len as usize - self.skip
+    }
+
+    /// Fetch the length of the entry value
+    pub fn value_len(&self) -> usize {
+        self.len
+    }
+
+    /// Read the entry key, returning the key length
+    pub fn read_key(&mut self, key: &mut [u8]) -> Result<usize, Error<E>> {
+        block_on(self.store.read_key(self.addr, &self.header, self.skip, key))
+    }
+
+    /// Read the entry value, returning the value length
+    pub fn read_value(&mut self, value: &mut [u8]) -> Result<usize, Error<E>> {
+        block_on(self.store.read_value(self.addr, &self.header, value))
+    }
+}
+
+/// Iterator over the latest value of each key, created by [`AsyncKvs::iter`](crate::AsyncKvs::iter)
+pub struct AsyncIter<'a, F: AsyncFlash, I: KeyIndex = NoIndex> {
+    store: &'a mut Store<F, I>,
+    cursor: Cursor,
+    prefix: &'a [u8],
+}
+
+impl<'a, F, E, I> AsyncIter<'a, F, I>
+where
+    F: AsyncFlash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    pub(crate) fn new(store: &'a mut Store<F, I>, prefix: &'a [u8]) -> Self {
+        Self {
+            store,
+            cursor: Cursor::default(),
+            prefix,
+        }
+    }
+
+    /// Fetch the next entry, returning None once all entries have been visited
+    pub async fn next(&mut self) -> Result<Option<AsyncEntry<'_, F, I>>, Error<E>> {
+        let (addr, header) = match self.store.next_live(&mut self.cursor, self.prefix).await? {
+            Some(e) => e,
+            None => return Ok(None),
+        };
+        let len = self.store.value_len(addr, &header).await?;
+
+        Ok(Some(AsyncEntry {
+            store: self.store,
+            addr,
+            header,
+            len,
+            skip: self.prefix.len(),
+        }))
+    }
+}
+
+/// Entry visited by an [`AsyncIter`]
+pub struct AsyncEntry<'a, F: AsyncFlash, I: KeyIndex = NoIndex> {
+    store: &'a mut Store<F, I>,
+    addr: usize,
+    header: EntryHeader,
+    /// Length of the value, which may be stored in chunks
+    len: usize,
+    /// Length of the key prefix to strip
+    skip: usize,
+}
+
+impl<'a, F, E, I> AsyncEntry<'a, F, I>
+where
+    F: AsyncFlash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    /// Fetch the length of the entry key
+    pub fn key_len(&self) -> usize {
+        self.header.key_len as usize - self.skip
+    }
+
+    /// Fetch the length of the entry value
+    pub fn value_len(&self) -> usize {
+        self.len
+    }
+
+    /// Read the entry key, returning the key length
+    pub async fn read_key(&mut self, key: &mut [u8]) -> Result<usize, Error<E>> {
+        self.store
+            .read_key(self.addr, &self.header, self.skip, key)
+            .await
+    }
+
+    /// Read the entry value, returning the value length
+    pub async fn read_value(&mut self, value: &mut [u8]) -> Result<usize, Error<E>> {
+        self.store.read_value(self.addr, &self.header, value).await
+    }
+}
@@ -1,15 +1,79 @@
 #![no_std]
 
-use core::fmt::Debug;
+use core::fmt::{self, Debug};
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
 
-use log::debug;
-use bitflags::bitflags;
+use log::{debug, warn};
+
+mod blob;
+
+mod header;
+pub use header::PageKind;
+use header::*;
+
+mod index;
+pub use index::{Index, KeyIndex, NoIndex, Slot};
+
+mod iter;
+pub use iter::{AsyncEntry, AsyncIter, Entry, Iter};
+
+mod migrate;
+
+mod namespace;
+pub use namespace::{AsyncNamespace, Namespace};
+
+mod transaction;
+use transaction::staged;
+pub use transaction::{AsyncTransaction, Transaction};
+
+#[cfg(feature = "serde")]
+mod typed;
+
+mod writer;
+pub use writer::{AsyncWriter, Writer};
+
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+#[cfg(feature = "embedded-storage")]
+pub mod nor_flash;
+
+#[cfg(test)]
+mod test;
 
 /// Flash trait describes page-erasable flash
 pub trait Flash {
     /// Flash page size (minimum erasable chunk)
     const PAGE_SIZE: usize;
 
+    /// Flash write size (minimum programmable chunk)
+    ///
+    /// Writes are aligned to and a multiple of this size, and each word is
+    /// programmed only once between erases. Must be a power of two no
+    /// larger than [`MAX_WRITE_SIZE`], stores over flash with other write
+    /// sizes fail to compile:
+    ///
+    /// ```compile_fail
+    /// # use fkvs::{Flash, Kvs, Options};
+    /// struct Odd;
+    ///
+    /// impl Flash for Odd {
+    ///     const PAGE_SIZE: usize = 2048;
+    ///     const WRITE_SIZE: usize = 3;
+    ///     type Error = ();
+    /// #   fn read(&mut self, _: usize, _: &mut [u8]) -> Result<(), ()> { Ok(()) }
+    /// #   fn write(&mut self, _: usize, _: &[u8]) -> Result<(), ()> { Ok(()) }
+    /// #   fn erase_page(&mut self, _: usize) -> Result<(), ()> { Ok(()) }
+    /// #   fn capacity(&self) -> usize { 4096 }
+    /// }
+    ///
+    /// let opts = Options::builder().build(&Odd).unwrap();
+    /// let kvs = Kvs::new(Odd, opts);
+    /// ```
+    const WRITE_SIZE: usize = 1;
+
     /// Flash operation error
     type Error: Debug;
 
@@ -24,9 +88,76 @@ pub trait Flash {
 
     /// Erase a flash page by address
     fn erase_page(&mut self, addr: usize) -> Result<(), Self::Error>;
+
+    /// Total size of the flash device in bytes
+    ///
+    /// Store options are checked to fit within this size, so a store can not
+    /// be configured to write beyond the end of the device
+    fn capacity(&self) -> usize;
+}
+
+/// AsyncFlash trait describes page-erasable flash with asynchronous operations
+#[allow(async_fn_in_trait)]
+pub trait AsyncFlash {
+    /// Flash page size (minimum erasable chunk)
+    const PAGE_SIZE: usize;
+
+    /// Flash write size (minimum programmable chunk), see [`Flash::WRITE_SIZE`]
+    const WRITE_SIZE: usize = 1;
+
+    /// Flash operation error
+    type Error: Debug;
+
+    /// Read data from flash
+    async fn read(&mut self, addr: usize, data: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Write data to flash, see [`Flash::write`]
+    async fn write(&mut self, addr: usize, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Erase a flash page by address
+    async fn erase_page(&mut self, addr: usize) -> Result<(), Self::Error>;
+
+    /// Total size of the flash device in bytes, see [`Flash::capacity`]
+    fn capacity(&self) -> usize;
+}
+
+/// Adapter driving blocking [`Flash`] through the shared store implementation
+struct Blocking<F>(F);
+
+impl<F: Flash> AsyncFlash for Blocking<F> {
+    const PAGE_SIZE: usize = F::PAGE_SIZE;
+
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+
+    type Error = F::Error;
+
+    async fn read(&mut self, addr: usize, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.read(addr, data)
+    }
+
+    async fn write(&mut self, addr: usize, data: &[u8]) -> Result<(), Self::Error> {
+        self.0.write(addr, data)
+    }
+
+    async fn erase_page(&mut self, addr: usize) -> Result<(), Self::Error> {
+        self.0.erase_page(addr)
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
 }
 
-/// Options for Key Value Store configuration
+/// Maximum supported flash write size
+pub const MAX_WRITE_SIZE: usize = 32;
+
+/// Maximum key length for any page size
+///
+/// Keys are further limited to leave room in a page for the headers and at
+/// least one byte of value, longer keys return [`Error::KeyTooLong`]
+pub const MAX_KEY_LEN: usize = u16::MAX as usize;
+
+/// Options for Key Value Store configuration, created with [`Options::builder`]
 #[derive(Clone, PartialEq, Debug)]
 pub struct Options {
     /// Flash KVS start address
@@ -35,10 +166,162 @@ pub struct Options {
     num_pages: usize,
 }
 
+impl Options {
+    /// Create a builder for store options
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
+
+    /// Check options against the flash geometry
+    fn check(&self, page_size: usize, capacity: usize) -> Result<(), ConfigError> {
+        if !self.start_addr.is_multiple_of(page_size) {
+            return Err(ConfigError::Unaligned);
+        }
+
+        // Collection requires a free page to copy into
+        if self.num_pages < 2 {
+            return Err(ConfigError::TooFewPages);
+        }
+
+        let end = self
+            .num_pages
+            .checked_mul(page_size)
+            .and_then(|len| len.checked_add(self.start_addr));
+        match end {
+            Some(end) if end <= capacity => Ok(()),
+            _ => Err(ConfigError::OutOfBounds),
+        }
+    }
+}
+
+/// Builder for [`Options`], checking the store fits the flash geometry
+///
+/// ```
+/// # #[cfg(feature = "mock")] {
+/// use fkvs::{Options, mock::MockKvs};
+///
+/// let flash = MockKvs::<2048, 4>::new();
+/// let opts = Options::builder()
+///     .start_addr(0)
+///     .num_pages(4)
+///     .build(&flash)
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Clone, PartialEq, Debug)]
+pub struct OptionsBuilder {
+    start_addr: usize,
+    num_pages: usize,
+}
+
+impl Default for OptionsBuilder {
+    fn default() -> Self {
+        Self {
+            start_addr: 0,
+            num_pages: 2,
+        }
+    }
+}
+
+impl OptionsBuilder {
+    /// Set the flash address of the first page, defaults to 0
+    pub fn start_addr(mut self, start_addr: usize) -> Self {
+        self.start_addr = start_addr;
+        self
+    }
+
+    /// Set the number of pages used by the store, defaults to 2
+    pub fn num_pages(mut self, num_pages: usize) -> Self {
+        self.num_pages = num_pages;
+        self
+    }
+
+    /// Build options for a store over the provided flash
+    pub fn build<F: Flash>(self, flash: &F) -> Result<Options, ConfigError> {
+        self.check(F::PAGE_SIZE, flash.capacity())
+    }
+
+    /// Build options for a store over the provided [`AsyncFlash`]
+    pub fn build_async<F: AsyncFlash>(self, flash: &F) -> Result<Options, ConfigError> {
+        self.check(F::PAGE_SIZE, flash.capacity())
+    }
+
+    fn check(self, page_size: usize, capacity: usize) -> Result<Options, ConfigError> {
+        let opts = Options {
+            start_addr: self.start_addr,
+            num_pages: self.num_pages,
+        };
+        opts.check(page_size, capacity)?;
+        Ok(opts)
+    }
+}
+
+/// Store configuration errors
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    /// Start address is not aligned to a flash page
+    Unaligned,
+    /// Fewer than two pages were configured
+    TooFewPages,
+    /// Store extends beyond the end of the flash
+    OutOfBounds,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Unaligned => write!(f, "start address not aligned to a page"),
+            ConfigError::TooFewPages => write!(f, "at least two pages required"),
+            ConfigError::OutOfBounds => write!(f, "store exceeds flash size"),
+        }
+    }
+}
+
+/// Store errors, wrapping errors from the underlying flash
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error<E> {
     /// Underlying flash error
     Flash(E),
+    /// No entry found for the requested key
+    NotFound,
+    /// Provided buffer is too small for the stored value
+    BufferTooSmall,
+    /// Entry data failed CRC check
+    Corrupt,
+    /// No space available for the entry
+    Full,
+    /// Options do not fit the flash geometry
+    Config(ConfigError),
+    /// Store was written with an unsupported format version
+    UnsupportedVersion(u8),
+    /// Key exceeds the maximum key length
+    KeyTooLong,
+    /// Typed value could not be encoded
+    Encode,
+    /// Stored value could not be decoded as the requested type
+    Decode,
+    /// Data written does not match the length of the value being written
+    LengthMismatch,
+}
+
+impl<E: Debug> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Flash(e) => write!(f, "flash error: {:?}", e),
+            Error::NotFound => write!(f, "key not found"),
+            Error::BufferTooSmall => write!(f, "buffer too small for value"),
+            Error::Corrupt => write!(f, "corrupt entry"),
+            Error::Full => write!(f, "store full"),
+            Error::Config(e) => write!(f, "invalid options: {}", e),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            Error::KeyTooLong => write!(f, "key too long"),
+            Error::Encode => write!(f, "value could not be encoded"),
+            Error::Decode => write!(f, "value could not be decoded"),
+            Error::LengthMismatch => write!(f, "value length mismatch"),
+        }
+    }
 }
 
 impl<E> From<E> for Error<E> {
@@ -47,179 +330,1806 @@ impl<E> From<E> for Error<E> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-#[repr(u8)]
-pub enum PageKind {
-    /// Standard K:V data page
-    Standard = 0x00,
+/// Key Value Store over blocking [`Flash`], optionally with a [`KeyIndex`]
+pub struct Kvs<F: Flash, I: KeyIndex = NoIndex> {
+    store: Store<Blocking<F>, I>,
+}
+
+impl<F, E> Kvs<F>
+where
+    F: Flash<Error = E>,
+    E: Debug,
+{
+    /// Create a store over the provided flash, mounting existing data or
+    /// formatting the store if none is found
+    pub fn new(flash: F, opts: Options) -> Result<Self, Error<E>> {
+        Self::with_index(flash, opts, NoIndex)
+    }
 }
 
-bitflags!(
-  struct PageFlags: u16 {
-    /// Default to all bits set for FLASH erased
-    const DEFAULT = 0xFFFF;
+impl<F, E, I> Kvs<F, I>
+where
+    F: Flash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    /// Create a store over the provided flash with an index of keys, see
+    /// [`Index`]
+    pub fn with_index(flash: F, opts: Options, index: I) -> Result<Self, Error<E>> {
+        let store = block_on(Store::new(Blocking(flash), opts, index))?;
+        Ok(Self { store })
+    }
+
+    /// Format the file system, erasing all content and resetting to the initial state
+    pub fn format(&mut self) -> Result<(), Error<E>> {
+        block_on(self.store.format())
+    }
+
+    /// Read a chunk of data from the file system
+    pub fn read(&mut self, key: &[u8], value: &mut [u8]) -> Result<usize, Error<E>> {
+        block_on(self.store.read(&[key], value))
+    }
+
+    /// Read part of a value starting at a byte offset, returning the number
+    /// of bytes read
+    ///
+    /// Reads stop at the end of the value, returning no bytes at or beyond
+    /// the end. The CRC of each entry read from is checked in full.
+    pub fn read_at(
+        &mut self,
+        key: &[u8],
+        offset: usize,
+        value: &mut [u8],
+    ) -> Result<usize, Error<E>> {
+        block_on(self.store.read_at(&[key], offset, value))
+    }
+
+    /// Write a chunk of data to the file system
+    ///
+    /// Values too large for a single page are split into chunks across
+    /// pages, and committed together once every chunk is written
+    pub fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error<E>> {
+        block_on(self.store.write(&[key], value))
+    }
+
+    /// Delete a key from the file system
+    ///
+    /// This writes a tombstone superseding existing entries for the key, and
+    /// does nothing if the key is not found
+    pub fn delete(&mut self, key: &[u8]) -> Result<(), Error<E>> {
+        block_on(self.store.delete(&[key]))
+    }
+
+    /// Fetch the number of times each page has been erased, returning the
+    /// number of pages
+    ///
+    /// Counts are recorded in page headers, so survive formatting the store.
+    pub fn wear(&mut self, erases: &mut [u32]) -> Result<usize, Error<E>> {
+        block_on(self.store.wear(erases))
+    }
+
+    /// Iterate over the latest value of each key, see [`Iter`]
+    pub fn iter(&mut self) -> Iter<'_, F, I> {
+        Iter::new(&mut self.store, &[])
+    }
+
+    /// Start a transaction, staging operations in the provided buffer, see
+    /// [`Transaction`]
+    pub fn transaction<'a>(&'a mut self, buff: &'a mut [u8]) -> Transaction<'a, F, I> {
+        Transaction::new(self, buff)
+    }
 
-    /// Indicates a page is not in use (clear to activeate)
-    const INACTIVE = (1 << 0);
+    /// Start writing a value of `len` bytes in pieces, buffering pieces in
+    /// the provided buffer, see [`Writer`]
+    pub fn writer<'a>(
+        &'a mut self,
+        key: &'a [u8],
+        len: usize,
+        buff: &'a mut [u8],
+    ) -> Result<Writer<'a, F, I>, Error<E>> {
+        Writer::new(self, [&[], key], len, buff)
+    }
+}
 
-    /// Indicates a page is valid (clear to invalidate)
-    const VALID = (1 << 1);
-  }
-);
+/// Key Value Store over [`AsyncFlash`]
+///
+/// This shares the on-flash format and implementation with [`Kvs`], so
+/// either may mount a store written by the other.
+pub struct AsyncKvs<F: AsyncFlash, I: KeyIndex = NoIndex> {
+    store: Store<F, I>,
+}
 
-/// PageHeader identifies a flash pages in the NVS
-#[derive(Debug, Clone, PartialEq)]
-struct PageHeader {
-    /// File system version ID, MUST be 1
-    version: u8,
-    /// Page kind, specifies how the page should be read
-    kind: PageKind,
-    /// Page index, wrapping monotonic count
-    index: u32,
-    /// Page usage flags
-    flags: PageFlags,
+impl<F, E> AsyncKvs<F>
+where
+    F: AsyncFlash<Error = E>,
+    E: Debug,
+{
+    /// Create a store over the provided flash, mounting existing data or
+    /// formatting the store if none is found
+    pub async fn new(flash: F, opts: Options) -> Result<Self, Error<E>> {
+        Self::with_index(flash, opts, NoIndex).await
+    }
 }
 
-bitflags!(
-  struct EntryFlags: u16 {
-    /// Default to all bits set for FLASH erased
-    const DEFAULT = 0xFFFF;
+impl<F, E, I> AsyncKvs<F, I>
+where
+    F: AsyncFlash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    /// Create a store over the provided flash with an index of keys, see
+    /// [`Index`]
+    pub async fn with_index(flash: F, opts: Options, index: I) -> Result<Self, Error<E>> {
+        let store = Store::new(flash, opts, index).await?;
+        Ok(Self { store })
+    }
+
+    /// Format the file system, erasing all content and resetting to the initial state
+    pub async fn format(&mut self) -> Result<(), Error<E>> {
+        self.store.format().await
+    }
+
+    /// Read a chunk of data from the file system
+    pub async fn read(&mut self, key: &[u8], value: &mut [u8]) -> Result<usize, Error<E>> {
+        self.store.read(&[key], value).await
+    }
+
+    /// Read part of a value starting at a byte offset, see [`Kvs::read_at`]
+    pub async fn read_at(
+        &mut self,
+        key: &[u8],
+        offset: usize,
+        value: &mut [u8],
+    ) -> Result<usize, Error<E>> {
+        self.store.read_at(&[key], offset, value).await
+    }
+
+    /// Write a chunk of data to the file system, see [`Kvs::write`]
+    pub async fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error<E>> {
+        self.store.write(&[key], value).await
+    }
+
+    /// Delete a key from the file system, see [`Kvs::delete`]
+    pub async fn delete(&mut self, key: &[u8]) -> Result<(), Error<E>> {
+        self.store.delete(&[key]).await
+    }
+
+    /// Fetch the number of times each page has been erased, see [`Kvs::wear`]
+    pub async fn wear(&mut self, erases: &mut [u32]) -> Result<usize, Error<E>> {
+        self.store.wear(erases).await
+    }
+
+    /// Iterate over the latest value of each key, see [`AsyncIter`]
+    pub fn iter(&mut self) -> AsyncIter<'_, F, I> {
+        AsyncIter::new(&mut self.store, &[])
+    }
+
+    /// Start a transaction, staging operations in the provided buffer, see
+    /// [`Transaction`]
+    pub fn transaction<'a>(&'a mut self, buff: &'a mut [u8]) -> AsyncTransaction<'a, F, I> {
+        AsyncTransaction::new(self, buff)
+    }
 
-    /// Indicates an entry is not in use (clear to activate)
-    const INACTIVE = (1 << 0);
+    /// Start writing a value of `len` bytes in pieces, see [`Writer`]
+    pub async fn writer<'a>(
+        &'a mut self,
+        key: &'a [u8],
+        len: usize,
+        buff: &'a mut [u8],
+    ) -> Result<AsyncWriter<'a, F, I>, Error<E>> {
+        AsyncWriter::new(self, [&[], key], len, buff).await
+    }
+}
 
-    /// Indicates an entry is valid (clear to invalidate)
-    const VALID = (1 << 1);
-  }
-);
+/// Compute the total length of data provided in parts
+fn parts_len(parts: &[&[u8]]) -> usize {
+    parts.iter().map(|p| p.len()).sum()
+}
 
-#[derive(Debug, Clone, PartialEq)]
-struct EntryHeader {
-    /// Entry index, per-key wrapping monotonic count
-    index: u16,
+/// Drive a future to completion, polling until ready
+///
+/// Store operations over blocking [`Flash`] never wait, so complete on the
+/// first poll.
+fn block_on<T>(f: impl Future<Output = T>) -> T {
+    let mut f = pin!(f);
+    let mut cx = Context::from_waker(Waker::noop());
 
-    flags: EntryFlags,
+    loop {
+        if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+            return v;
+        }
+    }
+}
 
-    key_len: u16,
+/// Position within the store when walking entries
+#[derive(Clone, Debug, Default)]
+struct Cursor {
+    /// Current page number and age
+    page: Option<(usize, u32)>,
+    /// Age of the previous page
+    age: Option<u32>,
+    /// Offset of the next entry within the current page
+    offset: usize,
+}
 
-    val_len: u16,
+/// Space needed to write new entries
+#[derive(Clone, Copy, Debug)]
+enum Space {
+    /// An entry of the provided length in the active page
+    Bytes(usize),
+    /// A value of `len` bytes written in chunks, in pieces of at most `piece`
+    /// bytes
+    Chunks {
+        key_len: usize,
+        len: usize,
+        piece: usize,
+    },
 }
 
-pub struct Kvs<F: Flash> {
+/// Store implementation shared by [`Kvs`] and [`AsyncKvs`]
+struct Store<F: AsyncFlash, I: KeyIndex = NoIndex> {
     flash: F,
     opts: Options,
 
     page_active: u32,
     page_offset: u32,
+    page_index: u32,
+
+    index: I,
+    /// Whether the index holds every live key
+    indexed: bool,
 }
 
-impl<F, E> Kvs<F>
+impl<F, E, I> Store<F, I>
 where
-    F: Flash<Error = E>,
+    F: AsyncFlash<Error = E>,
     E: Debug,
+    I: KeyIndex,
 {
-    pub fn new(flash: F, opts: Options) -> Result<Self, Error<E>> {
-        let mut s = Self { flash, opts, page_active: 0, page_offset: 0 };
+    /// Length of page headers and offset of the first entry in a page
+    const PAGE_HEADER_LEN: usize = PageHeader::len(F::WRITE_SIZE);
 
-        s.init()?;        
+    /// Length of entry headers and offset of the key in an entry
+    const ENTRY_HEADER_LEN: usize = EntryHeader::len(F::WRITE_SIZE);
+
+    /// Maximum key length, leaving space in a page for a chunk holding the
+    /// key, its offset and one byte of value
+    const MAX_KEY_LEN: usize = {
+        let len = (F::PAGE_SIZE - Self::PAGE_HEADER_LEN - Self::ENTRY_HEADER_LEN)
+            .saturating_sub(align(blob::OFFSET_LEN + 1, F::WRITE_SIZE));
+        if len < MAX_KEY_LEN {
+            len
+        } else {
+            MAX_KEY_LEN
+        }
+    };
+
+    async fn new(flash: F, opts: Options, index: I) -> Result<Self, Error<E>> {
+        const {
+            assert!(
+                F::WRITE_SIZE.is_power_of_two() && F::WRITE_SIZE <= MAX_WRITE_SIZE,
+                "Unsupported flash write size"
+            )
+        };
+
+        opts.check(F::PAGE_SIZE, flash.capacity())
+            .map_err(Error::Config)?;
+
+        let mut s = Self {
+            flash,
+            opts,
+            page_active: 0,
+            page_offset: 0,
+            page_index: 0,
+            index,
+            indexed: false,
+        };
+
+        s.init().await?;
 
         Ok(s)
     }
 
-    fn init(&mut self) -> Result<usize, Error<E>> {
-      // Attempt to find existing / latest KVS page
-      let mut current_index = None;
-      for i in 0..self.opts.num_pages {
-          // Read page header
-          let h = self.get_page_header(i * F::PAGE_SIZE)?;
+    async fn init(&mut self) -> Result<(), Error<E>> {
+        self.indexed = false;
 
-          // Skip inactive pages
-          if h.flags.contains(PageFlags::INACTIVE) {
-            continue;
-          }
+        // Attempt to find existing / latest KVS page
+        if !self.mount().await? {
+            debug!("FKVS no index found, re-formatting");
+
+            return self.format().await;
+        }
 
-          // Skip expired pages
-          if !h.flags.contains(PageFlags::VALID) {
-            continue;
-          }
+        self.recover().await?;
+        self.migrate().await?;
+        self.build_index().await
+    }
 
-          // Track current indez
-          match current_index {
-            Some(ref mut c) if *c < h.index => *c = h.index,
-            Some(_) => (),
-            None => current_index = Some(h.index),
-          }
-      }
+    /// Locate the active page and recover the write offset, returning false
+    /// if no pages are in use or a format was interrupted
+    async fn mount(&mut self) -> Result<bool, Error<E>> {
+        let mut current = None;
+        for i in 0..self.opts.num_pages {
+            let h = match self.get_page_header(self.page_addr(i)).await? {
+                Header::Valid(h) => h,
+                _ => continue,
+            };
 
-      match current_index {
-        Some(i) => {
-          debug!("FKVS Initialising with current index: {}", i);
+            // Refuse stores written by newer versions, or by older versions
+            // that can't be migrated, before recovery writes to them
+            migrate::check_version(h.version)?;
 
-          self.page_active = i;
+            // Skip free pages
+            if !h.is_live() {
+                continue;
+            }
 
-          unimplemented!()
-        },
-        None => {
-          debug!("FKVS no index found, re-formatting");
+            // A live reset page marks an interrupted format, which is
+            // finished by formatting again
+            if h.kind == PageKind::Reset {
+                debug!("FKVS found reset page {}, resuming format", i);
+                return Ok(false);
+            }
 
-          self.format()?;
+            // Track current page and index
+            match current {
+                Some((_, c)) if !page_newer(h.index, c) => (),
+                _ => current = Some((i, h.index)),
+            }
         }
-      }
-    }
 
-    /// Format the file system, erasing all content and resetting to the initial state
-    fn format(&mut self) -> Result<usize, Error<E>> {
-      unimplemented!()
+        let (page, index) = match current {
+            Some(c) => c,
+            None => return Ok(false),
+        };
+
+        debug!(
+            "FKVS Initialising with current index: {} (page {})",
+            index, page
+        );
+
+        // Recover the write offset from the entries in the active page
+        self.page_active = page as u32;
+        self.page_index = index;
+        self.page_offset = self.scan_page(page).await? as u32;
+
+        debug!("FKVS active page offset: {}", self.page_offset);
+
+        Ok(true)
     }
 
-    /// Read a chunk of data from the file system
-    pub fn read(&mut self, key: &[u8], value: &mut [u8]) -> Result<usize, Error<E>> {
-        // TODO: locate (latest) existing entry
+    /// Recover from operations interrupted by power loss, rolling back
+    /// uncommitted entries and collection and completing invalidation
+    ///
+    /// The store is only walked entry by entry when the last entry written
+    /// shows an interrupted operation, so mounting after a clean shutdown
+    /// reads each entry header a bounded number of times.
+    async fn recover(&mut self) -> Result<(), Error<E>> {
+        // Collection consumes the reserve page, if none remain free then
+        // collection was interrupted. The active page then only holds copies
+        // of live entries so is discarded, and collection retried when next
+        // required.
+        if self.free_pages().await? == 0 {
+            debug!(
+                "FKVS discarding interrupted collection to page {}",
+                self.page_active
+            );
+
+            let addr = self.page_addr(self.page_active as usize);
+            self.clear_page_flag(addr, PageFlags::VALID).await?;
+            self.mount().await?;
+        }
+
+        // Only the last operation can have been interrupted, leaving its
+        // entries at the end of the store
+        let mut c = Cursor::default();
+        let mut last = None;
+        while let Some(e) = self.next(&mut c).await? {
+            last = Some(e);
+        }
+
+        let (addr, h) = match last {
+            Some(e) => e,
+            None => return Ok(()),
+        };
 
-        // TODO: read out header
+        // Transactions with a live commit marker are rolled forward
+        if h.is_commit() && h.is_live() {
+            debug!("FKVS completing committed transaction at 0x{:08x}", addr);
+            return self.apply_commit(addr).await;
+        }
+
+        if !self.is_interrupted(addr, &h).await? {
+            return Ok(());
+        }
+
+        let mut c = Cursor::default();
+        while let Some((addr, h)) = self.next(&mut c).await? {
+            if !h.flags.contains(EntryFlags::VALID) {
+                continue;
+            }
 
-        // TODO: read out entry data
+            if h.flags.contains(EntryFlags::INACTIVE) {
+                // Entries written but never activated are rolled back
+                debug!("FKVS rolling back inactive entry at 0x{:08x}", addr);
+                self.clear_entry_flag(addr, EntryFlags::VALID).await?;
+            } else if !h.is_chunk() && !self.is_latest(addr, &h).await? {
+                // Superseded entries are invalidated
+                debug!("FKVS invalidating superseded entry at 0x{:08x}", addr);
+                self.clear_entry_flag(addr, EntryFlags::VALID).await?;
+            }
+        }
+
+        // Then chunks of values that were superseded or never committed
+        let mut c = Cursor::default();
+        while let Some((addr, h)) = self.next(&mut c).await? {
+            if h.is_chunk() && h.is_live() && !self.is_latest(addr, &h).await? {
+                debug!("FKVS invalidating orphaned chunk at 0x{:08x}", addr);
+                self.clear_entry_flag(addr, EntryFlags::VALID).await?;
+            }
+        }
 
-        unimplemented!()
+        Ok(())
     }
 
-    /// Write a chunk of data to the file system
-    pub fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error<E>> {
-        // TODO: locate (latest) existing entry
+    /// Check whether the last entry written was left by an interrupted
+    /// operation
+    ///
+    /// Completed operations leave their last entry either invalidated, as for
+    /// transactions and entries rolled back by recovery, or live as the only
+    /// live entry for its key. Entries superseding others or copied by
+    /// collection are written after the entries they replace, and chunks
+    /// before their blob entry.
+    async fn is_interrupted(&mut self, addr: usize, h: &EntryHeader) -> Result<bool, Error<E>> {
+        if !h.flags.contains(EntryFlags::VALID) {
+            return Ok(false);
+        }
+        if h.flags.contains(EntryFlags::INACTIVE) || !h.is_record() {
+            return Ok(true);
+        }
+
+        let mut c = Cursor::default();
+        while let Some((a, e)) = self.next(&mut c).await? {
+            // Chunks of a value share the index of its blob entry
+            if a == addr
+                || !e.is_live()
+                || e.is_commit()
+                || e.key_len != h.key_len
+                || (h.is_blob() && e.is_chunk() && e.index == h.index)
+            {
+                continue;
+            }
+
+            if self
+                .flash_matches(
+                    a + Self::ENTRY_HEADER_LEN,
+                    addr + Self::ENTRY_HEADER_LEN,
+                    h.key_len as usize,
+                )
+                .await?
+            {
+                return Ok(true);
+            }
+        }
 
-        // TODO: check values do not already match
+        Ok(false)
+    }
+
+    /// Walk the entries in a page, returning the offset of the first free byte
+    async fn scan_page(&mut self, page: usize) -> Result<usize, Error<E>> {
+        let addr = self.page_addr(page);
+        let mut offset = self.first_entry(page).await?;
 
-        // TODO: find space for new entry
+        while offset + Self::ENTRY_HEADER_LEN <= F::PAGE_SIZE {
+            let h = match self.get_entry_header(addr + offset).await? {
+                Header::Valid(h) => h,
+                // An erased header marks the end of the written entries
+                Header::Erased => break,
+                // Corrupt headers can not be skipped, so no further entries
+                // may be appended to this page
+                Header::Corrupt => {
+                    warn!("FKVS corrupt entry header at 0x{:08x}", addr + offset);
+                    return Ok(F::PAGE_SIZE);
+                }
+            };
 
-        // TODO: write new entry
+            // As can lengths running off the end of the page
+            let next = offset + h.entry_len(F::WRITE_SIZE);
+            if next > F::PAGE_SIZE {
+                warn!("FKVS entry at 0x{:08x} exceeds page bounds", addr + offset);
+                return Ok(F::PAGE_SIZE);
+            }
 
-        // TODO: invalidate previous entry
+            offset = next;
+        }
 
-        unimplemented!()
+        Ok(offset)
     }
 
-    /// Erase all (available) pages
-    fn erase_all(&mut self) -> Result<(), Error<E>> {
+    /// Format the file system, erasing all content and resetting to the initial state
+    async fn format(&mut self) -> Result<(), Error<E>> {
+        debug!(
+            "FKVS formatting {} pages at 0x{:08x}",
+            self.opts.num_pages, self.opts.start_addr
+        );
+
+        // Mark the store as wiped before erasing any page holding entries,
+        // so that a format interrupted by power loss is finished on the next
+        // mount rather than leaving some of the entries behind
+        let (mut marker, mut live) = (None, false);
         for i in 0..self.opts.num_pages {
-            self.flash.erase_page(i * F::PAGE_SIZE)?;
+            match self.get_live_page(i).await? {
+                Some(h) if h.kind == PageKind::Reset => marker = Some(i),
+                Some(_) => live = true,
+                None => (),
+            }
+        }
+        if live && marker.is_none() {
+            marker = Some(self.mark_reset().await?);
+        }
+
+        // Erase all pages, the marker last, keeping erase counts in inactive
+        // headers, then activate the first page
+        let max = self.max_erases().await?;
+        let pages = (0..self.opts.num_pages)
+            .filter(|i| Some(*i) != marker)
+            .chain(marker);
+        for i in pages {
+            let erases = self.recorded_erases(i).await?.unwrap_or(max);
+            self.erase_page(i, PageKind::Standard, 0, erases).await?;
         }
+        let addr = self.page_addr(0);
+        self.clear_page_flag(addr, PageFlags::INACTIVE).await?;
+
+        self.page_active = 0;
+        self.page_offset = Self::PAGE_HEADER_LEN as u32;
+        self.page_index = 0;
+
+        index::clear(self.index.slots());
+        self.indexed = !self.index.slots().is_empty();
 
         Ok(())
     }
 
-    fn get_page_header(&self, addr: usize) -> Result<PageHeader, Error<E>> {
-        unimplemented!()
+    /// Read a chunk of data from the file system, with the key provided in
+    /// parts
+    async fn read(&mut self, key: &[&[u8]], value: &mut [u8]) -> Result<usize, Error<E>> {
+        // Locate (latest) existing entry
+        let (addr, h) = match self.find(key).await? {
+            Some((_, h)) if h.is_tombstone() => return Err(Error::NotFound),
+            Some(e) => e,
+            None => return Err(Error::NotFound),
+        };
+
+        self.read_value(addr, &h, value).await
+    }
+
+    /// Read the key of an entry, skipping a number of leading bytes
+    async fn read_key(
+        &mut self,
+        addr: usize,
+        h: &EntryHeader,
+        skip: usize,
+        key: &mut [u8],
+    ) -> Result<usize, Error<E>> {
+        let len = h.key_len as usize - skip;
+        if key.len() < len {
+            return Err(Error::BufferTooSmall);
+        }
+
+        self.flash
+            .read(addr + Self::ENTRY_HEADER_LEN + skip, &mut key[..len])
+            .await?;
+
+        Ok(len)
+    }
+
+    /// Read part of a value starting at a byte offset, with the key provided
+    /// in parts
+    async fn read_at(
+        &mut self,
+        key: &[&[u8]],
+        offset: usize,
+        value: &mut [u8],
+    ) -> Result<usize, Error<E>> {
+        let (addr, h) = match self.find(key).await? {
+            Some((_, h)) if h.is_tombstone() => return Err(Error::NotFound),
+            Some(e) => e,
+            None => return Err(Error::NotFound),
+        };
+
+        if h.is_blob() {
+            return self.read_blob_at(addr, &h, offset, value).await;
+        }
+
+        // Reads from the end of the value return no data
+        let len = usize::min(value.len(), (h.val_len as usize).saturating_sub(offset));
+        if len == 0 {
+            return Ok(0);
+        }
+
+        self.read_part(addr, &h, offset, &mut value[..len]).await?;
+
+        Ok(len)
     }
 
-    fn set_page_header(&mut self, addr: usize, ph: PageHeader) -> Result<(), Error<E>> {
-        unimplemented!()
+    /// Read part of the value of an entry, skipping a number of leading
+    /// bytes, and checking the data CRC over the whole entry
+    async fn read_part(
+        &mut self,
+        addr: usize,
+        h: &EntryHeader,
+        skip: usize,
+        value: &mut [u8],
+    ) -> Result<(), Error<E>> {
+        let data = addr + Self::ENTRY_HEADER_LEN + h.key_len as usize;
+        let end = skip + value.len();
+
+        // Read the part directly, and the data either side only for the CRC
+        let crc = self.key_crc(addr, h).await?;
+        let crc = self.flash_crc(crc, data, skip).await?;
+        self.flash.read(data + skip, value).await?;
+        let crc = crc_update(crc, value);
+        let crc = self
+            .flash_crc(crc, data + end, h.val_len as usize - end)
+            .await?;
+
+        if crc != h.crc {
+            warn!("FKVS data CRC mismatch for entry at 0x{:08x}", addr);
+            return Err(Error::Corrupt);
+        }
+
+        Ok(())
     }
 
-    fn get_entry_header(&self, addr: usize) -> Result<EntryHeader, Error<E>> {
-        unimplemented!()
+    /// Read the value of an entry, checking the data CRC
+    async fn read_value(
+        &mut self,
+        addr: usize,
+        h: &EntryHeader,
+        value: &mut [u8],
+    ) -> Result<usize, Error<E>> {
+        if h.is_blob() {
+            return self.read_blob(addr, h, value).await;
+        }
+
+        let len = h.val_len as usize;
+        if value.len() < len {
+            return Err(Error::BufferTooSmall);
+        }
+
+        // Read out entry data
+        self.flash
+            .read(
+                addr + Self::ENTRY_HEADER_LEN + h.key_len as usize,
+                &mut value[..len],
+            )
+            .await?;
+
+        let crc = self.key_crc(addr, h).await?;
+        if crc_update(crc, &value[..len]) != h.crc {
+            warn!("FKVS data CRC mismatch for entry at 0x{:08x}", addr);
+            return Err(Error::Corrupt);
+        }
+
+        Ok(len)
     }
 
-    fn set_entry_header(&mut self, addr: usize, ph: EntryHeader) -> Result<(), Error<E>> {
-        unimplemented!()
+    /// Write a chunk of data to the file system, with the key provided in
+    /// parts
+    async fn write(&mut self, key: &[&[u8]], value: &[u8]) -> Result<(), Error<E>> {
+        let key_len = parts_len(key);
+        if key_len > Self::MAX_KEY_LEN {
+            return Err(Error::KeyTooLong);
+        }
+
+        let crc = data_crc(key, value);
+
+        // Locate (latest) existing entry
+        let existing = self.find(key).await?;
+
+        // Check values do not already match, skipping the write if so
+        if let Some((addr, h)) = &existing {
+            if h.kind == EntryKind::Data
+                && h.val_len as usize == value.len()
+                && h.crc == crc
+                && self
+                    .data_matches(addr + Self::ENTRY_HEADER_LEN + key_len, &[value])
+                    .await?
+            {
+                debug!("FKVS skipping write, value unchanged");
+                return Ok(());
+            }
+        }
+
+        // Values too large for a single entry are written in chunks
+        if value.len() > u16::MAX as usize
+            || Self::ENTRY_HEADER_LEN + align(key_len + value.len(), F::WRITE_SIZE)
+                > F::PAGE_SIZE - Self::PAGE_HEADER_LEN
+        {
+            return self.write_blob(key, value, existing).await;
+        }
+
+        let h = EntryHeader {
+            index: match &existing {
+                Some((_, h)) => h.index.wrapping_add(1),
+                None => 0,
+            },
+            kind: EntryKind::Data,
+            flags: EntryFlags::DEFAULT,
+            key_len: key_len as u16,
+            val_len: value.len() as u16,
+            crc,
+        };
+        let len = h.entry_len(F::WRITE_SIZE);
+
+        // Find space for new entry, locating the existing entry again if
+        // garbage collection has relocated it
+        let existing = match self.reserve(len).await? {
+            true => self.find(key).await?,
+            false => existing,
+        };
+
+        // Write new entry
+        let addr = self.page_addr(self.page_active as usize) + self.page_offset as usize;
+        self.set_entry_header(addr, h.clone()).await?;
+        self.write_data(
+            addr + Self::ENTRY_HEADER_LEN,
+            key.iter().copied().chain([value]),
+        )
+        .await?;
+        self.page_offset += len as u32;
+
+        // Activate new entry once data is written
+        self.clear_entry_flag(addr, EntryFlags::INACTIVE).await?;
+
+        // Invalidate previous entry, along with any chunks of its value
+        match existing {
+            Some((_, e)) if e.is_blob() => self.invalidate_superseded(addr, &h).await?,
+            Some((a, _)) => self.clear_entry_flag(a, EntryFlags::VALID).await?,
+            None => (),
+        }
+
+        self.index_entry(addr, &h).await
+    }
+
+    /// Delete a key from the file system, with the key provided in parts
+    async fn delete(&mut self, key: &[&[u8]]) -> Result<(), Error<E>> {
+        // Locate the entry again if garbage collection relocates it while
+        // reserving space for the tombstone
+        loop {
+            // Skip keys that are missing or already deleted
+            let (addr, h) = match self.find(key).await? {
+                Some((addr, h)) if !h.is_tombstone() => (addr, h),
+                _ => return Ok(()),
+            };
+
+            if !self.reserve(self.tombstone_len(&h)).await? {
+                return self.write_tombstone(addr, &h).await;
+            }
+        }
+    }
+
+    /// Delete all keys starting with the provided prefix
+    async fn wipe(&mut self, prefix: &[u8]) -> Result<(), Error<E>> {
+        loop {
+            let (addr, h) = match self.next_live(&mut Cursor::default(), prefix).await? {
+                Some(e) => e,
+                None => return Ok(()),
+            };
+
+            if !self.reserve(self.tombstone_len(&h)).await? {
+                self.write_tombstone(addr, &h).await?;
+            }
+        }
+    }
+
+    /// Compute the length of a tombstone superseding an entry
+    fn tombstone_len(&self, h: &EntryHeader) -> usize {
+        Self::ENTRY_HEADER_LEN + align(h.key_len as usize, F::WRITE_SIZE)
+    }
+
+    /// Write a tombstone superseding an entry then invalidate the entries it
+    /// supersedes, copying the key from the entry
+    ///
+    /// Space for the tombstone must already be reserved in the active page
+    async fn write_tombstone(&mut self, addr: usize, h: &EntryHeader) -> Result<(), Error<E>> {
+        let t = EntryHeader {
+            index: h.index.wrapping_add(1),
+            kind: EntryKind::Tombstone,
+            flags: EntryFlags::DEFAULT,
+            key_len: h.key_len,
+            val_len: 0,
+            crc: self.key_crc(addr, h).await?,
+        };
+        let len = t.entry_len(F::WRITE_SIZE);
+
+        // Write tombstone, copying the key in chunks
+        let dest = self.page_addr(self.page_active as usize) + self.page_offset as usize;
+        self.set_entry_header(dest, t.clone()).await?;
+
+        let key_len = h.key_len as usize;
+        let mut buff = [0u8; MAX_WRITE_SIZE];
+        for offset in (0..key_len).step_by(buff.len()) {
+            let b = &mut buff[..usize::min(MAX_WRITE_SIZE, key_len - offset)];
+            self.flash
+                .read(addr + Self::ENTRY_HEADER_LEN + offset, b)
+                .await?;
+            self.write_data(dest + Self::ENTRY_HEADER_LEN + offset, [&b[..]])
+                .await?;
+        }
+        self.page_offset += len as u32;
+
+        // Activate tombstone once the key is written
+        self.clear_entry_flag(dest, EntryFlags::INACTIVE).await?;
+
+        // Indexed entries are the only live entry for their key, so only
+        // chunks of a value require walking the store
+        if self.indexed && !h.is_blob() {
+            self.clear_entry_flag(addr, EntryFlags::VALID).await?;
+            return self.index_entry(dest, &t).await;
+        }
+
+        // Invalidate all previous entries, wherever garbage collection has
+        // left them
+        let mut c = Cursor::default();
+        while let Some((a, e)) = self.next(&mut c).await? {
+            if a != dest
+                && e.is_live()
+                && e.key_len == h.key_len
+                && self
+                    .flash_matches(
+                        a + Self::ENTRY_HEADER_LEN,
+                        dest + Self::ENTRY_HEADER_LEN,
+                        key_len,
+                    )
+                    .await?
+            {
+                self.clear_entry_flag(a, EntryFlags::VALID).await?;
+            }
+        }
+
+        self.index_entry(dest, &t).await
+    }
+
+    /// Commit staged writes and deletes as a single transaction
+    ///
+    /// Entries are written inactive to the active page followed by a commit
+    /// marker. Activating the marker commits the transaction, then entries are
+    /// activated and superseded entries invalidated.
+    async fn commit(&mut self, ops: &[u8]) -> Result<(), Error<E>> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        // Reserve space for all entries and the marker in the active page
+        let marker_len = Self::ENTRY_HEADER_LEN + align(4, F::WRITE_SIZE);
+        let len = staged(ops).fold(marker_len, |len, (_, key, value)| {
+            len + Self::ENTRY_HEADER_LEN + align(key.len() + value.len(), F::WRITE_SIZE)
+        });
+        self.reserve(len).await?;
+
+        let page = self.page_addr(self.page_active as usize);
+        let first = self.page_offset;
+
+        for (kind, key, value) in staged(ops) {
+            let h = EntryHeader {
+                index: match self.find(&[key]).await? {
+                    Some((_, h)) => h.index.wrapping_add(1),
+                    None => 0,
+                },
+                kind,
+                flags: EntryFlags::DEFAULT,
+                key_len: key.len() as u16,
+                val_len: value.len() as u16,
+                crc: data_crc(&[key], value),
+            };
+            let len = h.entry_len(F::WRITE_SIZE);
+
+            let addr = page + self.page_offset as usize;
+            self.set_entry_header(addr, h).await?;
+            self.write_data(addr + Self::ENTRY_HEADER_LEN, [key, value])
+                .await?;
+            self.page_offset += len as u32;
+        }
+
+        // Write then activate the marker to commit
+        let first = first.to_le_bytes();
+        let h = EntryHeader {
+            index: 0,
+            kind: EntryKind::Commit,
+            flags: EntryFlags::DEFAULT,
+            key_len: 0,
+            val_len: first.len() as u16,
+            crc: data_crc(&[], &first),
+        };
+
+        let addr = page + self.page_offset as usize;
+        self.set_entry_header(addr, h).await?;
+        self.write_data(addr + Self::ENTRY_HEADER_LEN, [&first[..]])
+            .await?;
+        self.page_offset += marker_len as u32;
+
+        self.clear_entry_flag(addr, EntryFlags::INACTIVE).await?;
+
+        self.apply_commit(addr).await
+    }
+
+    /// Activate the entries of a committed transaction and invalidate the
+    /// entries they supersede, then invalidate the commit marker
+    async fn apply_commit(&mut self, marker: usize) -> Result<(), Error<E>> {
+        let page = self.page_addr((marker - self.opts.start_addr) / F::PAGE_SIZE);
+        let mut first = [0u8; 4];
+        self.flash
+            .read(marker + Self::ENTRY_HEADER_LEN, &mut first)
+            .await?;
+        let first = page + u32::from_le_bytes(first) as usize;
+
+        // Activate all entries
+        let mut addr = first;
+        while addr < marker {
+            let h = self.get_entry_header(addr).await?.valid()?;
+
+            if h.flags.contains(EntryFlags::INACTIVE) {
+                self.clear_entry_flag(addr, EntryFlags::INACTIVE).await?;
+            }
+            addr += h.entry_len(F::WRITE_SIZE);
+        }
+
+        // Then invalidate superseded entries
+        let mut addr = first;
+        while addr < marker {
+            let h = self.get_entry_header(addr).await?.valid()?;
+
+            self.invalidate_superseded(addr, &h).await?;
+            self.index_entry(addr, &h).await?;
+            addr += h.entry_len(F::WRITE_SIZE);
+        }
+
+        self.clear_entry_flag(marker, EntryFlags::VALID).await?;
+
+        Ok(())
+    }
+
+    /// Invalidate live entries for the key of an entry that it supersedes
+    async fn invalidate_superseded(
+        &mut self,
+        addr: usize,
+        h: &EntryHeader,
+    ) -> Result<(), Error<E>> {
+        let mut c = Cursor::default();
+        let mut after = false;
+
+        while let Some((a, e)) = self.next(&mut c).await? {
+            if a == addr {
+                after = true;
+                continue;
+            }
+
+            if !e.is_live() || e.is_commit() || e.key_len != h.key_len {
+                continue;
+            }
+
+            // Entries are superseded with an older index, or with the same
+            // index when appended earlier, and chunks unless they share the
+            // index of the entry
+            let older = match e.is_chunk() {
+                true => e.index != h.index,
+                false => index_newer(h.index, e.index) || (!after && e.index == h.index),
+            };
+            if older
+                && self
+                    .flash_matches(
+                        a + Self::ENTRY_HEADER_LEN,
+                        addr + Self::ENTRY_HEADER_LEN,
+                        h.key_len as usize,
+                    )
+                    .await?
+            {
+                self.clear_entry_flag(a, EntryFlags::VALID).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Walk the latest live entry for each key starting with the provided
+    /// prefix in append order, skipping superseded entries and deleted keys
+    async fn next_live(
+        &mut self,
+        c: &mut Cursor,
+        prefix: &[u8],
+    ) -> Result<Option<(usize, EntryHeader)>, Error<E>> {
+        while let Some((addr, h)) = self.next(c).await? {
+            if h.is_live()
+                && (h.kind == EntryKind::Data || h.is_blob())
+                && h.key_len as usize >= prefix.len()
+                && self
+                    .data_matches(addr + Self::ENTRY_HEADER_LEN, &[prefix])
+                    .await?
+                && self.is_latest(addr, &h).await?
+            {
+                return Ok(Some((addr, h)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Locate the latest active and valid entry for a key, returning the
+    /// entry address and header
+    async fn find(&mut self, key: &[&[u8]]) -> Result<Option<(usize, EntryHeader)>, Error<E>> {
+        if self.indexed {
+            return self.find_indexed(key).await;
+        }
+
+        let mut c = Cursor::default();
+        let mut latest: Option<(usize, EntryHeader)> = None;
+
+        // Walk entries in append order
+        while let Some((addr, h)) = self.next(&mut c).await? {
+            if h.is_live()
+                && h.is_record()
+                && h.key_len as usize == parts_len(key)
+                && self
+                    .data_matches(addr + Self::ENTRY_HEADER_LEN, key)
+                    .await?
+            {
+                // Later entries win unless the existing index is newer
+                match &latest {
+                    Some((_, l)) if index_newer(l.index, h.index) => (),
+                    _ => latest = Some((addr, h)),
+                }
+            }
+        }
+
+        Ok(latest)
+    }
+
+    /// Find the latest live entry for a key using the index
+    async fn find_indexed(
+        &mut self,
+        key: &[&[u8]],
+    ) -> Result<Option<(usize, EntryHeader)>, Error<E>> {
+        let mut p = index::Probe::new(data_crc(key, &[]));
+
+        while let Some((_, addr)) = p.next(self.index.slots()) {
+            let h = match self.get_entry_header(addr).await? {
+                Header::Valid(h) => h,
+                _ => continue,
+            };
+
+            if h.is_live()
+                && h.key_len as usize == parts_len(key)
+                && self
+                    .data_matches(addr + Self::ENTRY_HEADER_LEN, key)
+                    .await?
+            {
+                return Ok(Some((addr, h)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Record an entry as the latest for its key in the index, replacing the
+    /// previous entry for the key
+    async fn index_entry(&mut self, addr: usize, h: &EntryHeader) -> Result<(), Error<E>> {
+        if !self.indexed || !h.is_record() {
+            return Ok(());
+        }
+
+        let hash = self.key_crc(addr, h).await?;
+        let mut p = index::Probe::new(hash);
+
+        while let Some((pos, a)) = p.next(self.index.slots()) {
+            let e = match self.get_entry_header(a).await? {
+                Header::Valid(e) => e,
+                _ => continue,
+            };
+
+            if e.key_len == h.key_len
+                && self
+                    .flash_matches(
+                        a + Self::ENTRY_HEADER_LEN,
+                        addr + Self::ENTRY_HEADER_LEN,
+                        h.key_len as usize,
+                    )
+                    .await?
+            {
+                index::replace(self.index.slots(), pos, addr);
+                return Ok(());
+            }
+        }
+
+        // Lookups walk the store once the index overflows
+        if !index::insert(self.index.slots(), hash, addr) {
+            debug!("FKVS index full, falling back to walking entries");
+            self.indexed = false;
+        }
+
+        Ok(())
+    }
+
+    /// Rebuild the index from the live entries in the store
+    ///
+    /// Recovery must have invalidated superseded entries, so each live entry
+    /// is the latest for its key
+    async fn build_index(&mut self) -> Result<(), Error<E>> {
+        index::clear(self.index.slots());
+        self.indexed = !self.index.slots().is_empty();
+
+        let mut c = Cursor::default();
+        while self.indexed {
+            let (addr, h) = match self.next(&mut c).await? {
+                Some(e) => e,
+                None => break,
+            };
+
+            if h.is_live() && h.is_record() {
+                self.index_entry(addr, &h).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find the indexed entry for the key of an entry in flash
+    async fn find_indexed_at(
+        &mut self,
+        addr: usize,
+        h: &EntryHeader,
+    ) -> Result<Option<(usize, EntryHeader)>, Error<E>> {
+        let mut p = index::Probe::new(self.key_crc(addr, h).await?);
+
+        while let Some((_, a)) = p.next(self.index.slots()) {
+            if a == addr {
+                return Ok(Some((a, h.clone())));
+            }
+
+            let e = match self.get_entry_header(a).await? {
+                Header::Valid(e) => e,
+                _ => continue,
+            };
+
+            if e.key_len == h.key_len
+                && self
+                    .flash_matches(
+                        a + Self::ENTRY_HEADER_LEN,
+                        addr + Self::ENTRY_HEADER_LEN,
+                        h.key_len as usize,
+                    )
+                    .await?
+            {
+                return Ok(Some((a, e)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Check whether a live entry is the latest entry for its key
+    async fn is_latest(&mut self, addr: usize, h: &EntryHeader) -> Result<bool, Error<E>> {
+        if self.indexed && !h.is_commit() {
+            match self.find_indexed_at(addr, h).await? {
+                // The index holds the latest record for each key
+                Some((a, _)) if h.is_record() => return Ok(a == addr),
+                // Chunks can only belong to the indexed value, though copies
+                // left by collection are found by walking the store
+                Some((_, e)) if !e.is_blob() || e.index != h.index => return Ok(false),
+                _ => (),
+            }
+        }
+
+        if h.is_chunk() {
+            return self.is_latest_chunk(addr, h).await;
+        }
+
+        let mut c = Cursor::default();
+        let mut after = false;
+
+        while let Some((a, e)) = self.next(&mut c).await? {
+            if a == addr {
+                after = true;
+                continue;
+            }
+
+            if !e.is_live() || !e.is_record() || e.key_len != h.key_len {
+                continue;
+            }
+
+            // Entries supersede this one with a newer index, or with the
+            // same index when appended later
+            let newer = index_newer(e.index, h.index) || (after && e.index == h.index);
+            if newer
+                && self
+                    .flash_matches(
+                        a + Self::ENTRY_HEADER_LEN,
+                        addr + Self::ENTRY_HEADER_LEN,
+                        h.key_len as usize,
+                    )
+                    .await?
+            {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Check whether entries for the key of a tombstone remain outside of
+    /// the provided page, requiring the tombstone to be kept
+    async fn is_shadowing(
+        &mut self,
+        page: usize,
+        addr: usize,
+        h: &EntryHeader,
+    ) -> Result<bool, Error<E>> {
+        let start = self.page_addr(page);
+        let mut c = Cursor::default();
+
+        while let Some((a, e)) = self.next(&mut c).await? {
+            if (start..start + F::PAGE_SIZE).contains(&a) || e.key_len != h.key_len {
+                continue;
+            }
+
+            if self
+                .flash_matches(
+                    a + Self::ENTRY_HEADER_LEN,
+                    addr + Self::ENTRY_HEADER_LEN,
+                    h.key_len as usize,
+                )
+                .await?
+            {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Compute the total length of the latest live entries
+    async fn live_len(&mut self) -> Result<usize, Error<E>> {
+        let mut c = Cursor::default();
+        let mut len = 0;
+
+        while let Some((addr, h)) = self.next(&mut c).await? {
+            if h.is_live() && self.is_latest(addr, &h).await? {
+                len += h.entry_len(F::WRITE_SIZE);
+            }
+        }
+
+        Ok(len)
+    }
+
+    /// Ensure space is available in the active page for an entry of the
+    /// provided length, opening new pages and collecting garbage as required
+    ///
+    /// Returns true if existing entries have been relocated
+    async fn reserve(&mut self, len: usize) -> Result<bool, Error<E>> {
+        if len > F::PAGE_SIZE - Self::PAGE_HEADER_LEN {
+            return Err(Error::Full);
+        }
+
+        self.make_space(Space::Bytes(len)).await
+    }
+
+    /// Open pages and collect garbage until the space needed is available,
+    /// keeping one free page in reserve for garbage collection
+    ///
+    /// Returns true if existing entries have been relocated
+    async fn make_space(&mut self, space: Space) -> Result<bool, Error<E>> {
+        let mut moved = false;
+        for _ in 0..self.opts.num_pages * 2 + 1 {
+            let used = match space {
+                Space::Bytes(len) => {
+                    if self.page_offset as usize + len <= F::PAGE_SIZE {
+                        return Ok(moved);
+                    }
+                    if self.free_pages().await? > 1 {
+                        self.open_page().await?;
+                        continue;
+                    }
+                    len
+                }
+                Space::Chunks {
+                    key_len,
+                    len,
+                    piece,
+                } => {
+                    let (pages, used) =
+                        self.chunk_layout(key_len, len, piece).ok_or(Error::Full)?;
+                    if self.free_pages().await? > pages {
+                        return Ok(moved);
+                    }
+                    used
+                }
+            };
+
+            // Check live data will fit before shuffling pages
+            if !moved {
+                let capacity =
+                    self.opts.num_pages.saturating_sub(1) * (F::PAGE_SIZE - Self::PAGE_HEADER_LEN);
+                if self.live_len().await? + used > capacity {
+                    return Err(Error::Full);
+                }
+            }
+
+            self.collect().await?;
+            moved = true;
+        }
+
+        Err(Error::Full)
+    }
+
+    /// Collect garbage from the oldest page, copying the latest live entries
+    /// to the active page then invalidating the old page
+    async fn collect(&mut self) -> Result<(), Error<E>> {
+        let page = match self.next_page(None).await? {
+            Some((page, _)) => page,
+            None => return Ok(()),
+        };
+
+        debug!("FKVS collecting page {}", page);
+
+        // Compacting the active page requires a new page to copy into
+        if page == self.page_active as usize {
+            self.open_page().await?;
+        }
+
+        let mut offset = self.first_entry(page).await?;
+        while let Some(h) = self.next_entry(page, offset).await? {
+            let addr = self.page_addr(page) + offset;
+            offset += h.entry_len(F::WRITE_SIZE);
+
+            if !h.is_live() || !self.is_latest(addr, &h).await? {
+                continue;
+            }
+
+            // Tombstones are dropped once no entries remain for their key
+            if h.is_tombstone() && !self.is_shadowing(page, addr, &h).await? {
+                debug!("FKVS dropping tombstone at 0x{:08x}", addr);
+                if self.indexed {
+                    let hash = self.key_crc(addr, &h).await?;
+                    index::remove(self.index.slots(), hash, addr);
+                }
+                continue;
+            }
+
+            self.copy_entry(addr, &h).await?;
+        }
+
+        // Invalidate the old page now entries have been relocated
+        let addr = self.page_addr(page);
+        self.clear_page_flag(addr, PageFlags::VALID).await?;
+
+        Ok(())
+    }
+
+    /// Copy an entry to the active page, preserving the entry index
+    async fn copy_entry(&mut self, addr: usize, h: &EntryHeader) -> Result<(), Error<E>> {
+        let len = h.entry_len(F::WRITE_SIZE);
+        if self.page_offset as usize + len > F::PAGE_SIZE {
+            self.open_page().await?;
+        }
+
+        let dest = self.page_addr(self.page_active as usize) + self.page_offset as usize;
+        self.set_entry_header(
+            dest,
+            EntryHeader {
+                flags: EntryFlags::DEFAULT,
+                ..h.clone()
+            },
+        )
+        .await?;
+
+        // Copy padded key and value data
+        let mut buff = [0u8; MAX_WRITE_SIZE];
+        let mut offset = Self::ENTRY_HEADER_LEN;
+        while offset < len {
+            let b = &mut buff[..usize::min(MAX_WRITE_SIZE, len - offset)];
+            self.flash.read(addr + offset, b).await?;
+            self.flash.write(dest + offset, b).await?;
+            offset += b.len();
+        }
+        self.page_offset += len as u32;
+
+        self.clear_entry_flag(dest, EntryFlags::INACTIVE).await?;
+
+        self.index_entry(dest, h).await
+    }
+
+    /// Find the least worn free page and its erase count, preferring pages
+    /// following the active page where erase counts are equal
+    async fn free_page(&mut self) -> Result<Option<(usize, u32)>, Error<E>> {
+        let mut next: Option<(usize, u32)> = None;
+        for i in 1..=self.opts.num_pages {
+            let page = (self.page_active as usize + i) % self.opts.num_pages;
+            if self.get_live_page(page).await?.is_some() {
+                continue;
+            }
+
+            let erases = self.page_erases(page).await?;
+            match next {
+                Some((_, e)) if e <= erases => (),
+                _ => next = Some((page, erases)),
+            }
+        }
+
+        Ok(next)
+    }
+
+    /// Activate a reset page on the least worn free page, marking the store
+    /// as wiped, and return the page
+    async fn mark_reset(&mut self) -> Result<usize, Error<E>> {
+        let (page, erases) = self.free_page().await?.ok_or(Error::Full)?;
+        let index = self.page_index.wrapping_add(1);
+
+        debug!("FKVS marking reset on page {}", page);
+
+        self.erase_page(page, PageKind::Reset, index, erases)
+            .await?;
+        let addr = self.page_addr(page);
+        self.clear_page_flag(addr, PageFlags::INACTIVE).await?;
+
+        Ok(page)
+    }
+
+    /// Open the least worn free page as the active page
+    async fn open_page(&mut self) -> Result<(), Error<E>> {
+        let (page, erases) = self.free_page().await?.ok_or(Error::Full)?;
+        let index = self.page_index.wrapping_add(1);
+
+        debug!("FKVS opening page {} with index {}", page, index);
+
+        // Write the header to the erased page before activating it
+        self.erase_page(page, PageKind::Standard, index, erases)
+            .await?;
+        let addr = self.page_addr(page);
+        self.clear_page_flag(addr, PageFlags::INACTIVE).await?;
+
+        self.page_active = page as u32;
+        self.page_offset = Self::PAGE_HEADER_LEN as u32;
+        self.page_index = index;
+
+        Ok(())
+    }
+
+    /// Count pages not currently in use
+    async fn free_pages(&mut self) -> Result<usize, Error<E>> {
+        let mut free = 0;
+        for i in 0..self.opts.num_pages {
+            if self.get_live_page(i).await?.is_none() {
+                free += 1;
+            }
+        }
+
+        Ok(free)
+    }
+
+    /// Find the next in-use page from oldest to newest, returning the page
+    /// number and age relative to the active page
+    async fn next_page(&mut self, prev_age: Option<u32>) -> Result<Option<(usize, u32)>, Error<E>> {
+        let mut next: Option<(usize, u32)> = None;
+
+        for i in 0..self.opts.num_pages {
+            let h = match self.get_live_page(i).await? {
+                Some(h) => h,
+                None => continue,
+            };
+
+            // Skip pages not younger than the previous page
+            let age = self.page_index.wrapping_sub(h.index);
+            if let Some(p) = prev_age {
+                if age >= p {
+                    continue;
+                }
+            }
+
+            match next {
+                Some((_, a)) if a >= age => (),
+                _ => next = Some((i, age)),
+            }
+        }
+
+        Ok(next)
+    }
+
+    /// Walk entries in append order across all in-use pages, returning the
+    /// address and header of the next entry
+    async fn next(&mut self, c: &mut Cursor) -> Result<Option<(usize, EntryHeader)>, Error<E>> {
+        loop {
+            // Move to the next page once the current one is exhausted
+            let (page, age) = match c.page {
+                Some(p) => p,
+                None => match self.next_page(c.age).await? {
+                    Some(p) => {
+                        c.page = Some(p);
+                        c.offset = self.first_entry(p.0).await?;
+                        p
+                    }
+                    None => return Ok(None),
+                },
+            };
+
+            if let Some(h) = self.next_entry(page, c.offset).await? {
+                let addr = self.page_addr(page) + c.offset;
+                c.offset += h.entry_len(F::WRITE_SIZE);
+                return Ok(Some((addr, h)));
+            }
+
+            c.page = None;
+            c.age = Some(age);
+        }
+    }
+
+    /// Fetch the entry at an offset within a page, returning None at the end
+    /// of the written entries
+    async fn next_entry(
+        &mut self,
+        page: usize,
+        offset: usize,
+    ) -> Result<Option<EntryHeader>, Error<E>> {
+        if page == self.page_active as usize && offset >= self.page_offset as usize {
+            return Ok(None);
+        }
+        if offset + Self::ENTRY_HEADER_LEN > F::PAGE_SIZE {
+            return Ok(None);
+        }
+
+        match self.get_entry_header(self.page_addr(page) + offset).await? {
+            Header::Valid(h) if offset + h.entry_len(F::WRITE_SIZE) <= F::PAGE_SIZE => Ok(Some(h)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Compare data stored at two flash addresses
+    async fn flash_matches(&mut self, a: usize, b: usize, len: usize) -> Result<bool, Error<E>> {
+        let (mut buff_a, mut buff_b) = ([0u8; 16], [0u8; 16]);
+
+        let mut offset = 0;
+        while offset < len {
+            let n = usize::min(16, len - offset);
+            self.flash.read(a + offset, &mut buff_a[..n]).await?;
+            self.flash.read(b + offset, &mut buff_b[..n]).await?;
+
+            if buff_a[..n] != buff_b[..n] {
+                return Ok(false);
+            }
+
+            offset += n;
+        }
+
+        Ok(true)
+    }
+
+    /// Compare data stored in flash at the provided address, with the data
+    /// provided in parts
+    async fn data_matches(&mut self, addr: usize, data: &[&[u8]]) -> Result<bool, Error<E>> {
+        let mut buff = [0u8; 16];
+        let mut offset = 0;
+
+        for c in data.iter().flat_map(|d| d.chunks(16)) {
+            let b = &mut buff[..c.len()];
+            self.flash.read(addr + offset, b).await?;
+
+            if b != c {
+                return Ok(false);
+            }
+
+            offset += c.len();
+        }
+
+        Ok(true)
+    }
+
+    /// Compute the CRC over the key of an entry in flash
+    async fn key_crc(&mut self, addr: usize, h: &EntryHeader) -> Result<u32, Error<E>> {
+        self.flash_crc(0, addr + Self::ENTRY_HEADER_LEN, h.key_len as usize)
+            .await
+    }
+
+    /// Update a CRC with data stored in flash
+    async fn flash_crc(&mut self, mut crc: u32, addr: usize, len: usize) -> Result<u32, Error<E>> {
+        let mut buff = [0u8; 16];
+
+        for offset in (0..len).step_by(buff.len()) {
+            let b = &mut buff[..usize::min(16, len - offset)];
+            self.flash.read(addr + offset, b).await?;
+            crc = crc_update(crc, b);
+        }
+
+        Ok(crc)
+    }
+
+    /// Erase a page, writing an inactive header with the page kind and index
+    /// and the previous erase count incremented
+    async fn erase_page(
+        &mut self,
+        page: usize,
+        kind: PageKind,
+        index: u32,
+        erases: u32,
+    ) -> Result<(), Error<E>> {
+        let addr = self.page_addr(page);
+        self.flash.erase_page(addr).await?;
+
+        let h = PageHeader {
+            version: PageHeader::VERSION,
+            kind,
+            index,
+            erases: erases.saturating_add(1),
+            flags: PageFlags::DEFAULT,
+        };
+        self.set_page_header(addr, h).await
+    }
+
+    /// Fetch the erase count of a page
+    ///
+    /// Counts lost to an interrupted erase are taken as the highest count of
+    /// any page, so the page is not preferred when rotating
+    async fn page_erases(&mut self, page: usize) -> Result<u32, Error<E>> {
+        match self.recorded_erases(page).await? {
+            Some(n) => Ok(n),
+            None => self.max_erases().await,
+        }
+    }
+
+    /// Fetch the erase count recorded in a page header, if readable
+    async fn recorded_erases(&mut self, page: usize) -> Result<Option<u32>, Error<E>> {
+        match self.get_page_header(self.page_addr(page)).await? {
+            Header::Valid(h) => Ok(Some(h.erases)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Fetch the highest erase count recorded in any page header
+    async fn max_erases(&mut self) -> Result<u32, Error<E>> {
+        let mut max = 0;
+        for i in 0..self.opts.num_pages {
+            if let Some(n) = self.recorded_erases(i).await? {
+                max = u32::max(max, n);
+            }
+        }
+
+        Ok(max)
+    }
+
+    /// Fetch the erase count of each page, returning the number of pages
+    async fn wear(&mut self, erases: &mut [u32]) -> Result<usize, Error<E>> {
+        let n = self.opts.num_pages;
+        if erases.len() < n {
+            return Err(Error::BufferTooSmall);
+        }
+
+        for (i, e) in erases[..n].iter_mut().enumerate() {
+            *e = self.page_erases(i).await?;
+        }
+
+        Ok(n)
+    }
+
+    /// Fetch the address of a page by page number
+    fn page_addr(&self, page: usize) -> usize {
+        self.opts.start_addr + page * F::PAGE_SIZE
+    }
+
+    /// Read the header of an in-use page, returning None for free pages
+    async fn get_live_page(&mut self, page: usize) -> Result<Option<PageHeader>, Error<E>> {
+        match self.get_page_header(self.page_addr(page)).await? {
+            Header::Valid(h) if h.is_live() => Ok(Some(h)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Fetch the offset of the first entry in a page
+    async fn first_entry(&mut self, page: usize) -> Result<usize, Error<E>> {
+        match self.get_page_header(self.page_addr(page)).await? {
+            Header::Valid(h) => Ok(h.entries_offset(F::WRITE_SIZE)),
+            _ => Ok(Self::PAGE_HEADER_LEN),
+        }
+    }
+
+    /// Read and decode a page header
+    async fn get_page_header(&mut self, addr: usize) -> Result<Header<PageHeader>, Error<E>> {
+        let mut buff = [0u8; MAX_HEADER_LEN];
+        let buff = &mut buff[..Self::PAGE_HEADER_LEN];
+        self.flash.read(addr, buff).await?;
+
+        Ok(PageHeader::decode(buff, F::WRITE_SIZE))
+    }
+
+    /// Encode and write page header fields, leaving flags erased
+    async fn set_page_header(&mut self, addr: usize, ph: PageHeader) -> Result<(), Error<E>> {
+        let mut buff = [0u8; PageHeader::LEN];
+        ph.encode(&mut buff);
+
+        self.write_data(addr, [&buff[..]]).await?;
+
+        Ok(())
+    }
+
+    /// Read and decode an entry header
+    async fn get_entry_header(&mut self, addr: usize) -> Result<Header<EntryHeader>, Error<E>> {
+        let mut buff = [0u8; MAX_HEADER_LEN];
+        let buff = &mut buff[..Self::ENTRY_HEADER_LEN];
+        self.flash.read(addr, buff).await?;
+
+        Ok(EntryHeader::decode(buff, F::WRITE_SIZE))
+    }
+
+    /// Encode and write entry header fields, leaving flags erased
+    async fn set_entry_header(&mut self, addr: usize, eh: EntryHeader) -> Result<(), Error<E>> {
+        let mut buff = [0u8; EntryHeader::LEN];
+        eh.encode(&mut buff);
+
+        self.write_data(addr, [&buff[..]]).await?;
+
+        Ok(())
+    }
+
+    /// Clear a page flag by programming its word
+    ///
+    /// As flash bits may only be cleared this can only progress page state
+    async fn clear_page_flag(&mut self, addr: usize, flag: PageFlags) -> Result<(), Error<E>> {
+        let version = match self.get_page_header(addr).await? {
+            Header::Valid(h) => h.version,
+            _ => PageHeader::VERSION,
+        };
+
+        let offset = PageHeader::flag_offset(version, flag, F::WRITE_SIZE);
+        self.flash
+            .write(addr + offset, &[0u8; MAX_WRITE_SIZE][..F::WRITE_SIZE])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Clear an entry flag by programming its word
+    ///
+    /// As flash bits may only be cleared this can only progress entry state
+    async fn clear_entry_flag(&mut self, addr: usize, flag: EntryFlags) -> Result<(), Error<E>> {
+        let offset = EntryHeader::flag_offset(flag, F::WRITE_SIZE);
+        self.flash
+            .write(addr + offset, &[0u8; MAX_WRITE_SIZE][..F::WRITE_SIZE])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Write data spanning a number of slices, padding to the write size
+    ///
+    /// Returns the padded length written
+    async fn write_data<'d>(
+        &mut self,
+        addr: usize,
+        data: impl IntoIterator<Item = &'d [u8]>,
+    ) -> Result<usize, Error<E>> {
+        let mut buff = [0xFFu8; MAX_WRITE_SIZE];
+        let (mut n, mut offset) = (0, 0);
+
+        for mut d in data {
+            while !d.is_empty() {
+                let c = usize::min(d.len(), MAX_WRITE_SIZE - n);
+                buff[n..n + c].copy_from_slice(&d[..c]);
+                d = &d[c..];
+                n += c;
+
+                if n == MAX_WRITE_SIZE {
+                    self.flash.write(addr + offset, &buff).await?;
+                    offset += n;
+                    n = 0;
+                }
+            }
+        }
+
+        if n > 0 {
+            let len = align(n, F::WRITE_SIZE);
+            buff[n..len].fill(0xFF);
+            self.flash.write(addr + offset, &buff[..len]).await?;
+            offset += len;
+        }
+
+        Ok(offset)
     }
 }
@@ -0,0 +1,114 @@
+//! Migration of stores written with older format versions
+//!
+//! Each format version registers a step migrating stores from the previous
+//! version, which `init` runs in order from the oldest version of any in-use
+//! page. Stores with versions lacking a registered step, or newer than
+//! the current version, are refused with [`Error::UnsupportedVersion`].
+//!
+//! Steps are built from the store's own power-safe operations, so a
+//! migration interrupted by power loss resumes on the next mount.
+
+use core::fmt::Debug;
+
+use log::debug;
+
+use crate::header::PageHeader;
+use crate::{AsyncFlash, Error, KeyIndex, Store};
+
+/// Step migrating a store from one format version to the next
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    /// Rewrite older pages in the current layout by collecting them, for
+    /// versions changing only the page header
+    Collect,
+    /// Leave older pages in place, for versions only adding entry kinds
+    Compatible,
+}
+
+/// Registered migration steps by the version they migrate from
+const MIGRATIONS: &[(u8, Step)] = &[
+    // Version 4 adds page erase counts
+    (3, Step::Collect),
+    // Version 5 adds chunked values
+    (4, Step::Compatible),
+    // Version 6 adds reset pages marking an interrupted format
+    (5, Step::Compatible),
+];
+
+/// Fetch the step migrating from a version
+fn step(version: u8) -> Option<Step> {
+    MIGRATIONS
+        .iter()
+        .find(|(v, _)| *v == version)
+        .map(|(_, s)| *s)
+}
+
+/// Check a store with an in-use page of the provided version can be mounted,
+/// refusing newer versions and older versions that can't be migrated
+pub(crate) fn check_version<E>(version: u8) -> Result<(), Error<E>> {
+    let missing = (version..PageHeader::VERSION).any(|v| step(v).is_none());
+    match version > PageHeader::VERSION || missing {
+        true => Err(Error::UnsupportedVersion(version)),
+        false => Ok(()),
+    }
+}
+
+impl<F, E, I> Store<F, I>
+where
+    F: AsyncFlash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    /// Migrate in-use pages written with older format versions
+    pub(crate) async fn migrate(&mut self) -> Result<(), Error<E>> {
+        let oldest = match self.oldest_version().await? {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        for version in oldest..PageHeader::VERSION {
+            debug!("FKVS migrating from format version {}", version);
+
+            match step(version) {
+                Some(Step::Collect) => self.collect_version(version).await?,
+                Some(Step::Compatible) => (),
+                None => return Err(Error::UnsupportedVersion(version)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fetch the oldest format version of any in-use page
+    async fn oldest_version(&mut self) -> Result<Option<u8>, Error<E>> {
+        let mut oldest = None;
+        for i in 0..self.opts.num_pages {
+            if let Some(h) = self.get_live_page(i).await? {
+                oldest = Some(u8::min(h.version, oldest.unwrap_or(u8::MAX)));
+            }
+        }
+
+        Ok(oldest)
+    }
+
+    /// Collect pages until no in-use pages have the provided version
+    ///
+    /// Pages with older versions were written before any with newer
+    /// versions, so are always the oldest pages to be collected.
+    async fn collect_version(&mut self, version: u8) -> Result<(), Error<E>> {
+        loop {
+            let mut found = false;
+            for i in 0..self.opts.num_pages {
+                if let Some(h) = self.get_live_page(i).await? {
+                    found |= h.version == version;
+                }
+            }
+
+            if !found {
+                return Ok(());
+            }
+
+            self.collect().await?;
+        }
+    }
+}
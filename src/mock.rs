@@ -1,48 +1,279 @@
+//! In-RAM mock flash for testing against the store
+//!
+//! Enabled with the `mock` feature, `PAGE_SIZE`, `PAGES` and optionally
+//! `WRITE_SIZE` configure the flash geometry:
+//!
+//! ```
+//! # #[cfg(feature = "mock")] {
+//! use fkvs::mock::MockKvs;
+//!
+//! let mut flash = MockKvs::<2048, 4>::new();
+//! flash.set_strict(true);
+//! # }
+//! ```
 
-use core::fmt::Debug;
+use crate::Flash;
 
-use crate::Kvs;
+/// Mock flash errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockError {
+    /// Power was lost before the operation could complete
+    PowerLoss,
+    /// Strict write attempted to set a cleared bit at the provided address
+    BitSet(usize),
+    /// Strict write re-programmed a byte without clearing any further bits
+    DoubleWrite(usize),
+    /// Strict write crossed a page boundary
+    PageBoundary(usize),
+    /// Strict write was not aligned to the write size
+    Unaligned(usize),
+}
+
+/// Point at which the mock flash loses power
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerCut {
+    /// Lose power after a number of write or erase operations
+    Ops(usize),
+    /// Lose power after a number of bytes have been written or erased,
+    /// partially programming or erasing the next byte
+    Bytes(usize),
+}
+
+/// Mock page-erasable flash with `PAGES` pages of `PAGE_SIZE` bytes,
+/// programmed in words of `WRITE_SIZE` bytes
+pub struct MockKvs<const PAGE_SIZE: usize, const PAGES: usize, const WRITE_SIZE: usize = 1> {
+    data: [[u8; PAGE_SIZE]; PAGES],
+
+    /// Enforce NOR flash programming semantics
+    strict: bool,
 
-pub struct MockKvs<D> {
-    data: D,
+    /// Configured power cut
+    cut: Option<PowerCut>,
+    /// Power state, cleared when a power cut occurs
+    powered: bool,
+    /// Write and erase operations since the power cut was configured
+    ops: usize,
+    /// Bytes written or erased since the power cut was configured
+    bytes: usize,
+    /// Read operations since the power cut was configured
+    reads: usize,
 }
 
-impl <D> MockKvs<D> 
-where 
-    D: AsRef<[u8]> + AsMut<[u8]> + Debug,
+impl<const PAGE_SIZE: usize, const PAGES: usize, const WRITE_SIZE: usize>
+    MockKvs<PAGE_SIZE, PAGES, WRITE_SIZE>
 {
-    pub fn new(data: D) -> Self {
-        let m = MockKvs{ data };
+    /// Create a new mock flash with all memory erased
+    pub fn new() -> Self {
+        MockKvs {
+            data: [[0xFF; PAGE_SIZE]; PAGES],
+            strict: false,
+            cut: None,
+            powered: true,
+            ops: 0,
+            bytes: 0,
+            reads: 0,
+        }
+    }
+
+    /// Fetch the underlying flash data
+    pub fn data(&self) -> &[u8] {
+        self.data.as_flattened()
+    }
+
+    /// Enable strict NOR flash semantics
+    ///
+    /// Strict writes may only clear bits, must be aligned to `WRITE_SIZE`,
+    /// and may not cross a page boundary. Words of more than one byte may be
+    /// programmed only once, while single bytes may be re-programmed so long
+    /// as at least one further bit is cleared.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Check a write against NOR flash semantics
+    fn check_write(&self, addr: usize, data: &[u8]) -> Result<(), MockError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        if !addr.is_multiple_of(WRITE_SIZE) || !data.len().is_multiple_of(WRITE_SIZE) {
+            return Err(MockError::Unaligned(addr));
+        }
+
+        if addr / PAGE_SIZE != (addr + data.len() - 1) / PAGE_SIZE {
+            return Err(MockError::PageBoundary(addr));
+        }
+
+        let d = self.data.as_flattened();
+        for (i, b) in data.iter().enumerate() {
+            let old = d[addr + i];
+
+            if b & !old != 0 {
+                return Err(MockError::BitSet(addr + i));
+            }
+            if old != 0xFF && b & old == old {
+                return Err(MockError::DoubleWrite(addr + i));
+            }
+        }
+
+        if WRITE_SIZE > 1 {
+            for (i, w) in d[addr..addr + data.len()].chunks(WRITE_SIZE).enumerate() {
+                if w.iter().any(|b| *b != 0xFF) {
+                    return Err(MockError::DoubleWrite(addr + i * WRITE_SIZE));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Configure a power cut, restoring power and resetting operation counts
+    pub fn set_power_cut(&mut self, cut: Option<PowerCut>) {
+        self.cut = cut;
+        self.powered = true;
+        self.ops = 0;
+        self.bytes = 0;
+        self.reads = 0;
+    }
+
+    /// Check whether power has been cut
+    pub fn powered(&self) -> bool {
+        self.powered
+    }
+
+    /// Fetch the number of write and erase operations performed
+    pub fn ops(&self) -> usize {
+        self.ops
+    }
+
+    /// Fetch the number of bytes written or erased
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Fetch the number of read operations performed
+    pub fn reads(&self) -> usize {
+        self.reads
+    }
+
+    /// Consume power budget for an operation of the provided length,
+    /// returning the number of bytes completed if power is lost
+    fn consume(&mut self, len: usize) -> Option<usize> {
+        self.ops += 1;
 
-        // TODO: erase all memory
+        let done = match self.cut {
+            Some(PowerCut::Ops(n)) if self.ops > n => Some(0),
+            Some(PowerCut::Bytes(n)) if self.bytes + len > n => Some(n - self.bytes),
+            _ => None,
+        };
 
-        m
+        match done {
+            Some(d) => {
+                self.bytes += d;
+                self.powered = false;
+            }
+            None => self.bytes += len,
+        }
+
+        done
     }
 }
 
-impl <D> Flash for MockKvs<D>
-where 
-    D: AsRef<[u8]> + AsMut<[u8]> + Debug,
+impl<const PAGE_SIZE: usize, const PAGES: usize, const WRITE_SIZE: usize> Default
+    for MockKvs<PAGE_SIZE, PAGES, WRITE_SIZE>
 {
-    const PAGE_SIZE: usize = 2048;
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PAGE_SIZE: usize, const PAGES: usize, const WRITE_SIZE: usize> Flash
+    for MockKvs<PAGE_SIZE, PAGES, WRITE_SIZE>
+{
+    const PAGE_SIZE: usize = PAGE_SIZE;
+
+    const WRITE_SIZE: usize = WRITE_SIZE;
+
+    type Error = MockError;
 
     fn read(&mut self, addr: usize, data: &mut [u8]) -> Result<(), Self::Error> {
-        let d = self.data.as_ref();
-        data.copy_from_slice(&d[addr..addr+data.len()]);
+        if !self.powered {
+            return Err(MockError::PowerLoss);
+        }
+
+        self.reads += 1;
+
+        let d = self.data.as_flattened();
+        data.copy_from_slice(&d[addr..addr + data.len()]);
         Ok(())
     }
 
     fn write(&mut self, addr: usize, data: &[u8]) -> Result<(), Self::Error> {
-        let d = self.data.as_mut();
-        (&mut d[addr..addr+data.len()]).copy_from_slice(data);
-        Ok(())
+        if !self.powered {
+            return Err(MockError::PowerLoss);
+        }
+
+        if self.strict {
+            self.check_write(addr, data)?;
+        }
+
+        let cut = self.consume(data.len());
+
+        let d = self.data.as_flattened_mut();
+        match cut {
+            None => {
+                program(&mut d[addr..addr + data.len()], data, self.strict);
+                Ok(())
+            }
+            Some(n) => {
+                program(&mut d[addr..addr + n], &data[..n], self.strict);
+
+                // Only program the low bits of the interrupted byte
+                if n < data.len() && matches!(self.cut, Some(PowerCut::Bytes(_))) {
+                    d[addr + n] &= data[n] | 0xF0;
+                }
+
+                Err(MockError::PowerLoss)
+            }
+        }
     }
 
     fn erase_page(&mut self, addr: usize) -> Result<(), Self::Error> {
-        let d = self.data.as_mut();
+        if !self.powered {
+            return Err(MockError::PowerLoss);
+        }
+
+        let cut = self.consume(PAGE_SIZE);
 
-        for i in 0..Self::PAGE_SIZE {
+        let d = self.data.as_flattened_mut();
+        let n = cut.unwrap_or(PAGE_SIZE);
+
+        for i in 0..n {
             d[addr + i] = 0xFF;
         }
+
+        match cut {
+            None => Ok(()),
+            Some(_) => {
+                // Only erase the low bits of the interrupted byte
+                if n < PAGE_SIZE && matches!(self.cut, Some(PowerCut::Bytes(_))) {
+                    d[addr + n] |= 0x0F;
+                }
+
+                Err(MockError::PowerLoss)
+            }
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        PAGE_SIZE * PAGES
     }
-}
\ No newline at end of file
+}
+
+/// Program flash data, clearing bits only in strict mode
+fn program(d: &mut [u8], data: &[u8], strict: bool) {
+    match strict {
+        true => d.iter_mut().zip(data).for_each(|(d, b)| *d &= b),
+        false => d.copy_from_slice(data),
+    }
+}
@@ -0,0 +1,157 @@
+//! Prefix-scoped views over a store
+//!
+//! A [`Namespace`] borrows a [`Kvs`] and prepends its prefix to every key,
+//! so components sharing a store can't collide so long as their prefixes
+//! differ. Keys are stored with the prefix attached, so each component
+//! should use a distinct prefix and no prefix should start with another.
+//!
+//! ```
+//! # #[cfg(feature = "mock")] {
+//! # use fkvs::{Kvs, Namespace, Options, mock::MockKvs};
+//! # fn setup(kvs: &mut Kvs<MockKvs<2048, 4>>) {
+//! let mut radio = Namespace::new(kvs, b"radio/");
+//! radio.write(b"channel", &[11]).unwrap();
+//!
+//! // Clear all radio settings
+//! radio.wipe().unwrap();
+//! # }
+//! # }
+//! ```
+
+use core::fmt::Debug;
+
+use crate::{
+    block_on, AsyncFlash, AsyncIter, AsyncKvs, AsyncWriter, Error, Flash, Iter, KeyIndex, Kvs,
+    NoIndex, Writer,
+};
+
+/// Prefix-scoped view over a [`Kvs`]
+pub struct Namespace<'a, F: Flash, I: KeyIndex = NoIndex> {
+    kvs: &'a mut Kvs<F, I>,
+    prefix: &'a [u8],
+}
+
+impl<'a, F, E, I> Namespace<'a, F, I>
+where
+    F: Flash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    /// Create a view over the provided store, prefixing keys with `prefix`
+    pub fn new(kvs: &'a mut Kvs<F, I>, prefix: &'a [u8]) -> Self {
+        Self { kvs, prefix }
+    }
+
+    /// Read a value from the namespace
+    pub fn read(&mut self, key: &[u8], value: &mut [u8]) -> Result<usize, Error<E>> {
+        block_on(self.kvs.store.read(&[self.prefix, key], value))
+    }
+
+    /// Read part of a value from the namespace, see [`Kvs::read_at`]
+    pub fn read_at(
+        &mut self,
+        key: &[u8],
+        offset: usize,
+        value: &mut [u8],
+    ) -> Result<usize, Error<E>> {
+        block_on(self.kvs.store.read_at(&[self.prefix, key], offset, value))
+    }
+
+    /// Write a value to the namespace
+    pub fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error<E>> {
+        block_on(self.kvs.store.write(&[self.prefix, key], value))
+    }
+
+    /// Start writing a value to the namespace in pieces, see [`Writer`]
+    pub fn writer<'b>(
+        &'b mut self,
+        key: &'b [u8],
+        len: usize,
+        buff: &'b mut [u8],
+    ) -> Result<Writer<'b, F, I>, Error<E>> {
+        Writer::new(self.kvs, [self.prefix, key], len, buff)
+    }
+
+    /// Delete a key from the namespace
+    pub fn delete(&mut self, key: &[u8]) -> Result<(), Error<E>> {
+        block_on(self.kvs.store.delete(&[self.prefix, key]))
+    }
+
+    /// Iterate over the latest value of each key in the namespace, with the
+    /// prefix stripped from keys
+    pub fn iter(&mut self) -> Iter<'_, F, I> {
+        Iter::new(&mut self.kvs.store, self.prefix)
+    }
+
+    /// Delete all keys in the namespace
+    pub fn wipe(&mut self) -> Result<(), Error<E>> {
+        block_on(self.kvs.store.wipe(self.prefix))
+    }
+}
+
+/// Prefix-scoped view over an [`AsyncKvs`], see [`Namespace`]
+pub struct AsyncNamespace<'a, F: AsyncFlash, I: KeyIndex = NoIndex> {
+    kvs: &'a mut AsyncKvs<F, I>,
+    prefix: &'a [u8],
+}
+
+impl<'a, F, E, I> AsyncNamespace<'a, F, I>
+where
+    F: AsyncFlash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    /// Create a view over the provided store, prefixing keys with `prefix`
+    pub fn new(kvs: &'a mut AsyncKvs<F, I>, prefix: &'a [u8]) -> Self {
+        Self { kvs, prefix }
+    }
+
+    /// Read a value from the namespace
+    pub async fn read(&mut self, key: &[u8], value: &mut [u8]) -> Result<usize, Error<E>> {
+        self.kvs.store.read(&[self.prefix, key], value).await
+    }
+
+    /// Read part of a value from the namespace, see [`Kvs::read_at`]
+    pub async fn read_at(
+        &mut self,
+        key: &[u8],
+        offset: usize,
+        value: &mut [u8],
+    ) -> Result<usize, Error<E>> {
+        self.kvs
+            .store
+            .read_at(&[self.prefix, key], offset, value)
+            .await
+    }
+
+    /// Write a value to the namespace
+    pub async fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error<E>> {
+        self.kvs.store.write(&[self.prefix, key], value).await
+    }
+
+    /// Start writing a value to the namespace in pieces, see [`Writer`]
+    pub async fn writer<'b>(
+        &'b mut self,
+        key: &'b [u8],
+        len: usize,
+        buff: &'b mut [u8],
+    ) -> Result<AsyncWriter<'b, F, I>, Error<E>> {
+        AsyncWriter::new(self.kvs, [self.prefix, key], len, buff).await
+    }
+
+    /// Delete a key from the namespace
+    pub async fn delete(&mut self, key: &[u8]) -> Result<(), Error<E>> {
+        self.kvs.store.delete(&[self.prefix, key]).await
+    }
+
+    /// Iterate over the latest value of each key in the namespace, with the
+    /// prefix stripped from keys
+    pub fn iter(&mut self) -> AsyncIter<'_, F, I> {
+        AsyncIter::new(&mut self.kvs.store, self.prefix)
+    }
+
+    /// Delete all keys in the namespace
+    pub async fn wipe(&mut self) -> Result<(), Error<E>> {
+        self.kvs.store.wipe(self.prefix).await
+    }
+}
@@ -0,0 +1,94 @@
+//! Adapter for [`embedded_storage`] NOR flash
+//!
+//! Enabled with the `embedded-storage` feature, [`NorFlashAdapter`] allows
+//! any [`NorFlash`] implementation to back a [`Kvs`](crate::Kvs). Pages map
+//! to `ERASE_SIZE` and the store's write size to `WRITE_SIZE`, with reads
+//! aligned to `READ_SIZE` by the adapter. Writes are passed straight through,
+//! as the store aligns every write to `WRITE_SIZE`.
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::{Flash, MAX_WRITE_SIZE};
+
+/// Adapter implementing [`Flash`] for any [`NorFlash`]
+pub struct NorFlashAdapter<T> {
+    inner: T,
+}
+
+impl<T: NorFlash> NorFlashAdapter<T> {
+    /// Create a new adapter wrapping a NOR flash
+    ///
+    /// Fails to compile if `READ_SIZE` or `WRITE_SIZE` exceed
+    /// [`MAX_WRITE_SIZE`]
+    pub fn new(inner: T) -> Self {
+        const {
+            assert!(
+                T::READ_SIZE <= MAX_WRITE_SIZE && T::WRITE_SIZE <= MAX_WRITE_SIZE,
+                "Unsupported flash read or write size"
+            )
+        };
+
+        Self { inner }
+    }
+
+    /// Fetch the wrapped flash
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consume the adapter, returning the wrapped flash
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: NorFlash> Flash for NorFlashAdapter<T> {
+    const PAGE_SIZE: usize = T::ERASE_SIZE;
+
+    const WRITE_SIZE: usize = T::WRITE_SIZE;
+
+    type Error = T::Error;
+
+    fn read(&mut self, addr: usize, data: &mut [u8]) -> Result<(), Self::Error> {
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let (a, remaining) = (addr + offset, data.len() - offset);
+
+            // Read aligned blocks directly
+            if a % T::READ_SIZE == 0 && remaining >= T::READ_SIZE {
+                let n = remaining - remaining % T::READ_SIZE;
+                self.inner.read(a as u32, &mut data[offset..offset + n])?;
+                offset += n;
+                continue;
+            }
+
+            // Bounce unaligned words via a buffer
+            let start = a - a % T::READ_SIZE;
+            let mut buff = [0u8; MAX_WRITE_SIZE];
+            self.inner.read(start as u32, &mut buff[..T::READ_SIZE])?;
+
+            let n = usize::min(start + T::READ_SIZE - a, remaining);
+            data[offset..offset + n].copy_from_slice(&buff[a - start..a - start + n]);
+            offset += n;
+        }
+
+        Ok(())
+    }
+
+    /// Write data to flash, which must be aligned to `WRITE_SIZE`
+    ///
+    /// Padding unaligned writes would program the surrounding words again,
+    /// so they are left to the flash to reject as `NotAligned`
+    fn write(&mut self, addr: usize, data: &[u8]) -> Result<(), Self::Error> {
+        self.inner.write(addr as u32, data)
+    }
+
+    fn erase_page(&mut self, addr: usize) -> Result<(), Self::Error> {
+        self.inner.erase(addr as u32, (addr + T::ERASE_SIZE) as u32)
+    }
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+}
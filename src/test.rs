@@ -1,6 +1,2237 @@
+use crate::header::*;
+use crate::mock::{MockError, MockKvs, PowerCut};
+use crate::*;
 
-use fkvs::*;
+#[test]
+fn page_header_encoding() {
+    let h = PageHeader {
+        version: PageHeader::VERSION,
+        kind: PageKind::Standard,
+        index: 0x01020304,
+        erases: 0x0A0B0C0D,
+        flags: PageFlags::DEFAULT,
+    };
 
+    let mut buff = [0xFFu8; PageHeader::len(4)];
+    let mut fields = [0u8; PageHeader::LEN];
+    h.encode(&mut fields);
+    buff[..PageHeader::LEN].copy_from_slice(&fields);
+    assert_eq!(
+        &buff[..10],
+        &[0x06, 0x00, 0x04, 0x03, 0x02, 0x01, 0x0D, 0x0C, 0x0B, 0x0A]
+    );
+    assert_eq!(PageHeader::len(1), 16);
+    assert_eq!(PageHeader::len(4), 24);
 
+    assert_eq!(PageHeader::decode(&buff, 4), Header::Valid(h.clone()));
 
+    // Flags are cleared by programming their word without invalidating the CRC
+    assert_eq!(
+        PageHeader::flag_offset(PageHeader::VERSION, PageFlags::INACTIVE, 4),
+        16
+    );
+    assert_eq!(
+        PageHeader::flag_offset(PageHeader::VERSION, PageFlags::VALID, 4),
+        20
+    );
+    buff[20] = 0x00;
+    match PageHeader::decode(&buff, 4) {
+        Header::Valid(d) => {
+            assert!(d.flags.contains(PageFlags::INACTIVE));
+            assert!(!d.flags.contains(PageFlags::VALID));
+        }
+        d => panic!("Unexpected header: {:?}", d),
+    }
 
+    // Other fields may not
+    buff[2] = 0x00;
+    assert_eq!(PageHeader::decode(&buff, 4), Header::Corrupt);
+
+    assert_eq!(
+        PageHeader::decode(&[0xFF; PageHeader::len(4)], 4),
+        Header::Erased
+    );
+}
+
+#[test]
+fn entry_header_encoding() {
+    let h = EntryHeader {
+        index: 0x0102,
+        kind: EntryKind::Data,
+        flags: EntryFlags::DEFAULT,
+        key_len: 3,
+        val_len: 4,
+        crc: data_crc(&[b"key"], b"abcd"),
+    };
+
+    let mut buff = [0xFFu8; EntryHeader::len(8)];
+    let mut fields = [0u8; EntryHeader::LEN];
+    h.encode(&mut fields);
+    buff[..EntryHeader::LEN].copy_from_slice(&fields);
+    assert_eq!(&buff[..6], &[0x02, 0x01, 0x03, 0x00, 0x04, 0x00]);
+
+    assert_eq!(EntryHeader::decode(&buff, 8), Header::Valid(h.clone()));
+    assert_eq!(EntryHeader::len(1), 17);
+    assert_eq!(EntryHeader::len(8), 32);
+    assert_eq!(h.entry_len(1), 17 + 7);
+    assert_eq!(h.entry_len(8), 32 + 8);
+
+    buff[EntryHeader::flag_offset(EntryFlags::INACTIVE, 8)] = 0x00;
+    match EntryHeader::decode(&buff, 8) {
+        Header::Valid(d) => assert!(!d.flags.contains(EntryFlags::INACTIVE)),
+        d => panic!("Unexpected header: {:?}", d),
+    }
+
+    // Tombstones are encoded with their kind
+    let t = EntryHeader {
+        kind: EntryKind::Tombstone,
+        val_len: 0,
+        ..h.clone()
+    };
+    let mut fields = [0u8; EntryHeader::LEN];
+    t.encode(&mut fields);
+    assert_eq!(fields[10], 0x01);
+    let mut buff_t = [0xFFu8; EntryHeader::len(1)];
+    buff_t[..EntryHeader::LEN].copy_from_slice(&fields);
+    assert_eq!(EntryHeader::decode(&buff_t, 1), Header::Valid(t));
+
+    buff[9] ^= 0x10;
+    assert_eq!(EntryHeader::decode(&buff, 8), Header::Corrupt);
+
+    assert_eq!(
+        EntryHeader::decode(&[0xFF; EntryHeader::len(8)], 8),
+        Header::Erased
+    );
+}
+
+#[test]
+fn data_crc_spans_key_and_value() {
+    assert_eq!(
+        data_crc(&[b"1234"], b"56789"),
+        crc::crc32::checksum_ieee(b"123456789")
+    );
+    assert_ne!(data_crc(&[b"key"], b"a"), data_crc(&[b"key"], b"b"));
+    assert_eq!(
+        data_crc(&[b"12", b"34"], b"56789"),
+        data_crc(&[b"1234"], b"56789")
+    );
+}
+
+#[test]
+fn options_builder() {
+    let flash = MockKvs::<2048, 4>::new();
+
+    let opts = Options::builder()
+        .start_addr(2048)
+        .num_pages(3)
+        .build(&flash);
+    assert_eq!(
+        opts,
+        Ok(Options {
+            start_addr: 2048,
+            num_pages: 3
+        })
+    );
+    assert_eq!(
+        Options::builder().build(&flash),
+        Ok(Options {
+            start_addr: 0,
+            num_pages: 2
+        })
+    );
+
+    let opts = Options::builder().start_addr(1024).build(&flash);
+    assert_eq!(opts, Err(ConfigError::Unaligned));
+    let opts = Options::builder().num_pages(1).build(&flash);
+    assert_eq!(opts, Err(ConfigError::TooFewPages));
+    let opts = Options::builder()
+        .start_addr(4096)
+        .num_pages(3)
+        .build(&flash);
+    assert_eq!(opts, Err(ConfigError::OutOfBounds));
+    let opts = Options::builder().num_pages(usize::MAX).build(&flash);
+    assert_eq!(opts, Err(ConfigError::OutOfBounds));
+
+    // Options are checked again when mounting
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 5,
+    };
+    assert!(matches!(
+        Kvs::new(flash, opts),
+        Err(Error::Config(ConfigError::OutOfBounds))
+    ));
+}
+
+#[test]
+fn error_display() {
+    use core::fmt::Write;
+
+    struct Buff([u8; 64], usize);
+    impl Write for Buff {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            self.0[self.1..][..s.len()].copy_from_slice(s.as_bytes());
+            self.1 += s.len();
+            Ok(())
+        }
+    }
+
+    let mut b = Buff([0; 64], 0);
+    write!(b, "{}", Error::<MockError>::UnsupportedVersion(3)).unwrap();
+    assert_eq!(&b.0[..b.1], b"unsupported format version 3");
+
+    let mut b = Buff([0; 64], 0);
+    write!(b, "{}", Error::Flash(MockError::PowerLoss)).unwrap();
+    assert_eq!(&b.0[..b.1], b"flash error: PowerLoss");
+
+    let mut b = Buff([0; 64], 0);
+    write!(
+        b,
+        "{}",
+        Error::<MockError>::Config(ConfigError::TooFewPages)
+    )
+    .unwrap();
+    assert_eq!(&b.0[..b.1], b"invalid options: at least two pages required");
+
+    let mut b = Buff([0; 64], 0);
+    write!(b, "{}", Error::<MockError>::LengthMismatch).unwrap();
+    assert_eq!(&b.0[..b.1], b"value length mismatch");
+}
+
+type MockStore = Kvs<MockKvs<2048, 4>>;
+
+fn mock_store() -> MockStore {
+    let mut flash = MockKvs::<2048, 4>::new();
+    flash.set_strict(true);
+    Kvs::new(
+        flash,
+        Options {
+            start_addr: 0,
+            num_pages: 4,
+        },
+    )
+    .unwrap()
+}
+
+/// Append a raw entry to the active page
+fn append(kvs: &mut MockStore, index: u16, flags: EntryFlags, key: &[u8], value: &[u8]) {
+    append_kind(kvs, EntryKind::Data, index, flags, key, value)
+}
+
+/// Append a raw entry of the provided kind to the active page
+fn append_kind(
+    kvs: &mut MockStore,
+    kind: EntryKind,
+    index: u16,
+    flags: EntryFlags,
+    key: &[u8],
+    value: &[u8],
+) {
+    let addr = kvs.store.page_addr(kvs.store.page_active as usize) + kvs.store.page_offset as usize;
+    let h = EntryHeader {
+        index,
+        kind,
+        flags,
+        key_len: key.len() as u16,
+        val_len: value.len() as u16,
+        crc: data_crc(&[key], value),
+    };
+
+    block_on(kvs.store.set_entry_header(addr, h.clone())).unwrap();
+    block_on(
+        kvs.store
+            .write_data(addr + EntryHeader::len(1), [key, value]),
+    )
+    .unwrap();
+    for f in [EntryFlags::INACTIVE, EntryFlags::VALID] {
+        if !flags.contains(f) {
+            block_on(kvs.store.clear_entry_flag(addr, f)).unwrap();
+        }
+    }
+
+    kvs.store.page_offset += h.entry_len(1) as u32;
+}
+
+#[test]
+fn read_latest_entry() {
+    let mut kvs = mock_store();
+    let live = EntryFlags::DEFAULT & !EntryFlags::INACTIVE;
+    let mut buff = [0u8; 16];
+
+    assert_eq!(kvs.read(b"key", &mut buff), Err(Error::NotFound));
+
+    append(&mut kvs, 0, live, b"key", b"first");
+    append(&mut kvs, 0, live, b"other", b"value");
+    append(&mut kvs, 1, live, b"key", b"second");
+    assert_eq!(kvs.read(b"key", &mut buff), Ok(6));
+    assert_eq!(&buff[..6], b"second");
+
+    // Invalidated and inactive entries are ignored
+    append(&mut kvs, 2, live & !EntryFlags::VALID, b"key", b"invalid");
+    append(&mut kvs, 3, EntryFlags::DEFAULT, b"key", b"inactive");
+    assert_eq!(kvs.read(b"key", &mut buff), Ok(6));
+    assert_eq!(&buff[..6], b"second");
+
+    // Newer indices win over append order, including across wrapping
+    append(&mut kvs, 0, live, b"key", b"stale");
+    assert_eq!(kvs.read(b"key", &mut buff), Ok(6));
+    append(&mut kvs, u16::MAX, live, b"wrap", b"old");
+    append(&mut kvs, 0, live, b"wrap", b"new");
+    assert_eq!(kvs.read(b"wrap", &mut buff), Ok(3));
+    assert_eq!(&buff[..3], b"new");
+
+    assert_eq!(
+        kvs.read(b"other", &mut buff[..4]),
+        Err(Error::BufferTooSmall)
+    );
+    assert_eq!(kvs.read(b"ke", &mut buff), Err(Error::NotFound));
+}
+
+#[test]
+fn read_after_remount() {
+    let mut kvs = mock_store();
+    let live = EntryFlags::DEFAULT & !EntryFlags::INACTIVE;
+    let mut buff = [0u8; 16];
+
+    append(&mut kvs, 0, live, b"key", b"value");
+    let offset = kvs.store.page_offset;
+
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    assert_eq!(kvs.store.page_offset, offset);
+    assert_eq!(kvs.read(b"key", &mut buff), Ok(5));
+    assert_eq!(&buff[..5], b"value");
+}
+
+#[test]
+fn write_read() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 16];
+
+    kvs.write(b"key", b"first").unwrap();
+    kvs.write(b"other", b"value").unwrap();
+    assert_eq!(kvs.read(b"key", &mut buff), Ok(5));
+    assert_eq!(&buff[..5], b"first");
+
+    kvs.write(b"key", b"second").unwrap();
+    assert_eq!(kvs.read(b"key", &mut buff), Ok(6));
+    assert_eq!(&buff[..6], b"second");
+    assert_eq!(kvs.read(b"other", &mut buff), Ok(5));
+    assert_eq!(&buff[..5], b"value");
+
+    // Previous entry is invalidated
+    let addr = kvs.store.page_addr(0) + PageHeader::len(1);
+    match block_on(kvs.store.get_entry_header(addr)).unwrap() {
+        Header::Valid(h) => assert!(!h.is_live()),
+        h => panic!("Unexpected header: {:?}", h),
+    }
+
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    assert_eq!(kvs.read(b"key", &mut buff), Ok(6));
+    assert_eq!(&buff[..6], b"second");
+}
+
+#[test]
+fn write_unchanged_is_skipped() {
+    let mut kvs = mock_store();
+
+    kvs.write(b"key", b"value").unwrap();
+    let offset = kvs.store.page_offset;
+
+    kvs.write(b"key", b"value").unwrap();
+    assert_eq!(kvs.store.page_offset, offset);
+
+    kvs.write(b"key", b"other").unwrap();
+    assert!(kvs.store.page_offset > offset);
+}
+
+#[test]
+fn rotate_and_collect_pages() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 64];
+
+    // Repeatedly overwriting keys fills pages and forces collection
+    for i in 0..500u32 {
+        let key = [b'k', (i % 7) as u8];
+        let value = [i as u8; 40];
+        kvs.write(&key, &value).unwrap();
+    }
+
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    assert!(kvs.store.page_index > 4);
+
+    for i in 493..500u32 {
+        let key = [b'k', (i % 7) as u8];
+        assert_eq!(kvs.read(&key, &mut buff), Ok(40));
+        assert_eq!(&buff[..40], &[i as u8; 40]);
+    }
+}
+
+#[test]
+fn collect_with_two_pages() {
+    let mut flash = MockKvs::<2048, 4>::new();
+    flash.set_strict(true);
+    let mut kvs = Kvs::new(
+        flash,
+        Options {
+            start_addr: 2048,
+            num_pages: 2,
+        },
+    )
+    .unwrap();
+    let mut buff = [0u8; 64];
+
+    for i in 0..200u32 {
+        kvs.write(b"a", &[i as u8; 50]).unwrap();
+        kvs.write(b"b", &i.to_le_bytes()).unwrap();
+    }
+
+    assert_eq!(kvs.read(b"a", &mut buff), Ok(50));
+    assert_eq!(&buff[..50], &[199; 50]);
+    assert_eq!(kvs.read(b"b", &mut buff), Ok(4));
+    assert_eq!(&buff[..4], &199u32.to_le_bytes());
+
+    // Pages outside the store are untouched
+    assert!(kvs.store.flash.0.data()[..2048].iter().all(|b| *b == 0xFF));
+    assert!(kvs.store.flash.0.data()[2048 * 3..]
+        .iter()
+        .all(|b| *b == 0xFF));
+}
+
+#[test]
+fn write_full() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 990];
+
+    // Three usable pages fit six entries, one page is held in reserve
+    let value = [0xAA; 990];
+    for i in 0..5u8 {
+        kvs.write(&[i], &value).unwrap();
+    }
+
+    // Updates are possible while there is space for the new copy
+    for i in 0..10u8 {
+        kvs.write(&[0], &[i; 990]).unwrap();
+    }
+
+    kvs.write(&[5], &value).unwrap();
+    assert_eq!(kvs.write(&[6], &value), Err(Error::Full));
+    assert_eq!(kvs.write(&[0], &[0x55; 990]), Err(Error::Full));
+    assert_eq!(kvs.write(&[7], &[0xAA; 2048]), Err(Error::Full));
+
+    assert_eq!(kvs.read(&[0], &mut buff), Ok(990));
+    assert_eq!(buff, [9; 990]);
+}
+
+#[test]
+fn delete_key() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 16];
+
+    kvs.write(b"key", b"value").unwrap();
+    kvs.write(b"other", b"value").unwrap();
+    kvs.delete(b"key").unwrap();
+    assert_eq!(kvs.read(b"key", &mut buff), Err(Error::NotFound));
+    assert_eq!(kvs.read(b"other", &mut buff), Ok(5));
+
+    // Deleting missing keys does nothing
+    let offset = kvs.store.page_offset;
+    kvs.delete(b"key").unwrap();
+    kvs.delete(b"missing").unwrap();
+    assert_eq!(kvs.store.page_offset, offset);
+
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    assert_eq!(kvs.read(b"key", &mut buff), Err(Error::NotFound));
+
+    // Deleted keys may be written again, superseding the tombstone
+    kvs.write(b"key", b"new").unwrap();
+    assert_eq!(kvs.read(b"key", &mut buff), Ok(3));
+    assert_eq!(&buff[..3], b"new");
+}
+
+#[test]
+fn delete_collected() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 16];
+
+    kvs.write(b"key", b"value").unwrap();
+    kvs.delete(b"key").unwrap();
+
+    // Rotate through every page, collecting the tombstone
+    for i in 0..200u8 {
+        kvs.write(b"other", &[i; 100]).unwrap();
+        assert_eq!(kvs.read(b"key", &mut buff), Err(Error::NotFound));
+    }
+
+    // Once no entries remain for the key the tombstone is dropped
+    assert_eq!(block_on(kvs.store.find(&[b"key"])), Ok(None));
+
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    assert_eq!(kvs.read(b"key", &mut buff), Err(Error::NotFound));
+}
+
+#[test]
+fn iterate_entries() {
+    let mut kvs = mock_store();
+    let (mut key, mut value) = ([0u8; 16], [0u8; 128]);
+
+    kvs.write(b"a", b"1").unwrap();
+    kvs.write(b"b", b"2").unwrap();
+    kvs.write(b"c", b"3").unwrap();
+    kvs.write(b"a", b"4").unwrap();
+    kvs.delete(b"b").unwrap();
+
+    // Open further pages so entries span several pages
+    for i in 0..30u8 {
+        kvs.write(b"d", &[i; 100]).unwrap();
+    }
+
+    // Each key is visited once in append order, with the latest value
+    let expected: [(&[u8], &[u8]); 3] = [(b"c", b"3"), (b"a", b"4"), (b"d", &[29; 100])];
+    let mut iter = kvs.iter();
+    for (k, v) in expected.iter() {
+        let mut e = iter.next().unwrap().unwrap();
+        assert_eq!((e.key_len(), e.value_len()), (k.len(), v.len()));
+
+        assert_eq!(e.read_key(&mut key), Ok(k.len()));
+        assert_eq!(&key[..k.len()], *k);
+        if v.len() > 1 {
+            assert_eq!(e.read_value(&mut value[..1]), Err(Error::BufferTooSmall));
+        }
+        assert_eq!(e.read_value(&mut value), Ok(v.len()));
+        assert_eq!(&value[..v.len()], *v);
+    }
+    assert!(iter.next().unwrap().is_none());
+}
+
+#[test]
+fn namespaces() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 16];
+
+    kvs.write(b"x", b"root").unwrap();
+    Namespace::new(&mut kvs, b"a/").write(b"x", b"a").unwrap();
+    Namespace::new(&mut kvs, b"a/").write(b"y", b"a").unwrap();
+    Namespace::new(&mut kvs, b"b/").write(b"x", b"b").unwrap();
+
+    // Keys are stored with the prefix attached
+    assert_eq!(kvs.read(b"a/x", &mut buff), Ok(1));
+    assert_eq!(kvs.read(b"x", &mut buff), Ok(4));
+
+    let mut a = Namespace::new(&mut kvs, b"a/");
+    assert_eq!(a.read(b"x", &mut buff), Ok(1));
+    assert_eq!(&buff[..1], b"a");
+    a.delete(b"y").unwrap();
+    assert_eq!(a.read(b"y", &mut buff), Err(Error::NotFound));
+    a.write(b"z", b"a").unwrap();
+
+    // Iteration visits only keys within the namespace, stripping the prefix
+    let mut iter = a.iter();
+    let mut e = iter.next().unwrap().unwrap();
+    assert_eq!(e.key_len(), 1);
+    assert_eq!(e.read_key(&mut buff), Ok(1));
+    assert_eq!(&buff[..1], b"x");
+    let mut e = iter.next().unwrap().unwrap();
+    assert_eq!(e.read_key(&mut buff), Ok(1));
+    assert_eq!(&buff[..1], b"z");
+    assert!(iter.next().unwrap().is_none());
+
+    // Wiping a namespace leaves other keys intact
+    a.wipe().unwrap();
+    assert!(a.iter().next().unwrap().is_none());
+    assert_eq!(kvs.read(b"a/x", &mut buff), Err(Error::NotFound));
+    assert_eq!(kvs.read(b"a/z", &mut buff), Err(Error::NotFound));
+    assert_eq!(kvs.read(b"x", &mut buff), Ok(4));
+    assert_eq!(Namespace::new(&mut kvs, b"b/").read(b"x", &mut buff), Ok(1));
+    assert_eq!(&buff[..1], b"b");
+}
+
+#[test]
+fn wipe_collected() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 100];
+
+    // Fill the store so wiping requires collection to make space
+    for i in 0..45u8 {
+        Namespace::new(&mut kvs, b"ns/")
+            .write(&[i], &[i; 100])
+            .unwrap();
+    }
+    kvs.write(b"other", b"value").unwrap();
+    let index = kvs.store.page_index;
+
+    // With only the reserve page free, opening a page requires collection
+    assert_eq!(block_on(kvs.store.free_pages()), Ok(1));
+    Namespace::new(&mut kvs, b"ns/").wipe().unwrap();
+    assert!(kvs.store.page_index != index);
+
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    for i in 0..45u8 {
+        assert_eq!(
+            kvs.read(&[b'n', b's', b'/', i], &mut buff),
+            Err(Error::NotFound)
+        );
+    }
+    assert_eq!(kvs.read(b"other", &mut buff), Ok(5));
+}
+
+#[test]
+fn recover_interrupted_delete() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 16];
+
+    kvs.write(b"key", b"old").unwrap();
+
+    // Activate a tombstone without invalidating the previous entry
+    let live = EntryFlags::DEFAULT & !EntryFlags::INACTIVE;
+    append_kind(&mut kvs, EntryKind::Tombstone, 1, live, b"key", b"");
+
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    let addr = kvs.store.page_addr(0) + PageHeader::len(1);
+    match block_on(kvs.store.get_entry_header(addr)).unwrap() {
+        Header::Valid(h) => assert!(!h.is_live()),
+        h => panic!("Unexpected header: {:?}", h),
+    }
+    assert_eq!(kvs.read(b"key", &mut buff), Err(Error::NotFound));
+}
+
+#[test]
+fn recover_uncommitted_entry() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 16];
+
+    kvs.write(b"key", b"old").unwrap();
+
+    // Write a new entry without activating it
+    let addr = kvs.store.page_addr(0) + kvs.store.page_offset as usize;
+    append(&mut kvs, 1, EntryFlags::DEFAULT, b"key", b"new");
+
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    match block_on(kvs.store.get_entry_header(addr)).unwrap() {
+        Header::Valid(h) => assert!(!h.flags.contains(EntryFlags::VALID)),
+        h => panic!("Unexpected header: {:?}", h),
+    }
+    assert_eq!(kvs.read(b"key", &mut buff), Ok(3));
+    assert_eq!(&buff[..3], b"old");
+}
+
+#[test]
+fn recover_superseded_entry() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 16];
+
+    kvs.write(b"key", b"old").unwrap();
+
+    // Activate a new entry without invalidating the previous one
+    append(
+        &mut kvs,
+        1,
+        EntryFlags::DEFAULT & !EntryFlags::INACTIVE,
+        b"key",
+        b"new",
+    );
+
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    let addr = kvs.store.page_addr(0) + PageHeader::len(1);
+    match block_on(kvs.store.get_entry_header(addr)).unwrap() {
+        Header::Valid(h) => assert!(!h.is_live()),
+        h => panic!("Unexpected header: {:?}", h),
+    }
+    assert_eq!(kvs.read(b"key", &mut buff), Ok(3));
+    assert_eq!(&buff[..3], b"new");
+}
+
+#[test]
+fn recover_interrupted_collection() {
+    let mut flash = MockKvs::<2048, 2>::new();
+    flash.set_strict(true);
+    let mut kvs = Kvs::new(
+        flash,
+        Options {
+            start_addr: 0,
+            num_pages: 2,
+        },
+    )
+    .unwrap();
+    let mut buff = [0u8; 16];
+
+    kvs.write(b"a", b"1").unwrap();
+    kvs.write(b"b", b"2").unwrap();
+    kvs.write(b"a", b"3").unwrap();
+
+    // Open the reserve page and copy a single entry as if collection
+    // had been interrupted
+    block_on(kvs.store.open_page()).unwrap();
+    let addr = kvs.store.page_addr(0) + PageHeader::len(1) + EntryHeader::len(1) + 2;
+    let h = match block_on(kvs.store.get_entry_header(addr)).unwrap() {
+        Header::Valid(h) => h,
+        h => panic!("Unexpected header: {:?}", h),
+    };
+    block_on(kvs.store.copy_entry(addr, &h)).unwrap();
+
+    // The partially filled page is discarded on remount
+    let offset = kvs.store.page_offset;
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    assert_eq!(kvs.store.page_active, 0);
+    assert_eq!(block_on(kvs.store.free_pages()), Ok(1));
+    assert_eq!(kvs.read(b"a", &mut buff), Ok(1));
+    assert_eq!(&buff[..1], b"3");
+    assert_eq!(kvs.read(b"b", &mut buff), Ok(1));
+    assert_eq!(&buff[..1], b"2");
+
+    // Then collection is retried, copying each key once
+    block_on(kvs.store.collect()).unwrap();
+    assert_eq!(kvs.store.page_active, 1);
+    assert_eq!(
+        kvs.store.page_offset,
+        offset + EntryHeader::len(1) as u32 + 2
+    );
+    assert_eq!(block_on(kvs.store.free_pages()), Ok(1));
+}
+
+/// Write a number of keys to a store, returning the flash
+fn keys_store<const N: usize>(keys: usize) -> MockKvs<2048, N> {
+    let mut flash = MockKvs::<2048, N>::new();
+    flash.set_strict(true);
+    let opts = Options {
+        start_addr: 0,
+        num_pages: N,
+    };
+    let mut kvs = Kvs::with_index(flash, opts, Index::<1024>::new()).unwrap();
+
+    for i in 0..keys {
+        let key = [b'k', (i >> 8) as u8, i as u8];
+        kvs.write(&key, &key).unwrap();
+    }
+
+    kvs.store.flash.0
+}
+
+#[test]
+fn mount_after_clean_shutdown() {
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 8,
+    };
+
+    // Mounting reads each entry a bounded number of times, with or without
+    // an index
+    let mut flash = keys_store::<8>(400);
+    flash.set_power_cut(None);
+    let kvs = Kvs::new(flash, opts.clone()).unwrap();
+    assert!(kvs.store.flash.0.reads() < 10 * 400);
+
+    let mut flash = kvs.store.flash.0;
+    flash.set_power_cut(None);
+    let kvs = Kvs::with_index(flash, opts, Index::<512>::new()).unwrap();
+    assert!(kvs.store.flash.0.reads() < 10 * 400);
+}
+
+#[test]
+fn transaction_commit() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 16];
+
+    kvs.write(b"a", b"old").unwrap();
+    kvs.write(b"c", b"old").unwrap();
+
+    let mut staging = [0u8; 64];
+    let mut tx = kvs.transaction(&mut staging);
+    tx.write(b"a", b"new").unwrap();
+    tx.write(b"b", b"new").unwrap();
+    tx.delete(b"c").unwrap();
+    assert_eq!(tx.write(b"d", &[0; 64]), Err(Error::BufferTooSmall));
+    tx.commit().unwrap();
+
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    assert_eq!(kvs.read(b"a", &mut buff), Ok(3));
+    assert_eq!(&buff[..3], b"new");
+    assert_eq!(kvs.read(b"b", &mut buff), Ok(3));
+    assert_eq!(&buff[..3], b"new");
+    assert_eq!(kvs.read(b"c", &mut buff), Err(Error::NotFound));
+    assert_eq!(kvs.read(b"d", &mut buff), Err(Error::NotFound));
+
+    // Only the latest entries and no commit markers remain live
+    let mut c = Cursor::default();
+    let mut live = 0;
+    while let Some((_, h)) = block_on(kvs.store.next(&mut c)).unwrap() {
+        if h.is_live() {
+            assert!(!h.is_commit());
+            live += 1;
+        }
+    }
+    assert_eq!(live, 3);
+
+    // Keys staged twice take the last value
+    let mut tx = kvs.transaction(&mut staging);
+    tx.write(b"a", b"first").unwrap();
+    tx.write(b"a", b"last").unwrap();
+    tx.commit().unwrap();
+    assert_eq!(kvs.read(b"a", &mut buff), Ok(4));
+    assert_eq!(&buff[..4], b"last");
+}
+
+#[test]
+fn transaction_dropped() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 16];
+
+    kvs.write(b"a", b"old").unwrap();
+    let offset = kvs.store.page_offset;
+
+    // Discard the transaction without committing
+    {
+        let mut staging = [0u8; 64];
+        let mut tx = kvs.transaction(&mut staging);
+        tx.write(b"a", b"new").unwrap();
+        tx.delete(b"a").unwrap();
+    }
+
+    assert_eq!(kvs.store.page_offset, offset);
+    assert_eq!(kvs.read(b"a", &mut buff), Ok(3));
+    assert_eq!(&buff[..3], b"old");
+}
+
+#[test]
+fn recover_committed_transaction() {
+    let mut kvs = mock_store();
+    let mut buff = [0u8; 16];
+
+    kvs.write(b"a", b"old").unwrap();
+
+    // Write transaction entries and activate the marker without activating
+    // the entries
+    let first = kvs.store.page_offset;
+    append(&mut kvs, 1, EntryFlags::DEFAULT, b"a", b"new");
+    append(&mut kvs, 0, EntryFlags::DEFAULT, b"b", b"new");
+    let live = EntryFlags::DEFAULT & !EntryFlags::INACTIVE;
+    append_kind(
+        &mut kvs,
+        EntryKind::Commit,
+        0,
+        live,
+        b"",
+        &first.to_le_bytes(),
+    );
+
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    assert_eq!(kvs.read(b"a", &mut buff), Ok(3));
+    assert_eq!(&buff[..3], b"new");
+    assert_eq!(kvs.read(b"b", &mut buff), Ok(3));
+    assert_eq!(&buff[..3], b"new");
+
+    let addr = kvs.store.page_addr(0) + PageHeader::len(1);
+    match block_on(kvs.store.get_entry_header(addr)).unwrap() {
+        Header::Valid(h) => assert!(!h.is_live()),
+        h => panic!("Unexpected header: {:?}", h),
+    }
+}
+
+#[test]
+fn index_lookups() {
+    let mut flash = MockKvs::<2048, 4>::new();
+    flash.set_strict(true);
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 4,
+    };
+    let mut index = Index::<8>::new();
+    let mut kvs = Kvs::with_index(flash, opts.clone(), &mut index).unwrap();
+    let keys: [&[u8]; 4] = [b"a", b"bb", b"ccc", b"dddd"];
+
+    // Rotate through every page with writes, deletes and transactions,
+    // checking lookups through the index match the entries in flash
+    for i in 0..100u8 {
+        let k = keys[i as usize % keys.len()];
+        match i % 5 {
+            3 => kvs.delete(k).unwrap(),
+            4 => {
+                let mut staging = [0u8; 256];
+                let mut tx = kvs.transaction(&mut staging);
+                tx.write(k, &[i; 40]).unwrap();
+                tx.write(b"a", &[i; 20]).unwrap();
+                tx.commit().unwrap();
+            }
+            _ => kvs.write(k, &[i; 100]).unwrap(),
+        }
+        assert!(kvs.store.indexed);
+
+        for k in keys {
+            kvs.store.indexed = false;
+            let walked = block_on(kvs.store.find(&[k])).unwrap();
+            kvs.store.indexed = true;
+            assert_eq!(
+                block_on(kvs.store.find(&[k])).unwrap(),
+                walked,
+                "Index mismatch for key {:?}",
+                k
+            );
+        }
+    }
+
+    // Remounting rebuilds the index
+    let mut kvs = Kvs::with_index(kvs.store.flash.0, opts, &mut index).unwrap();
+    assert!(kvs.store.indexed);
+    for k in keys {
+        kvs.store.indexed = false;
+        let walked = block_on(kvs.store.find(&[k])).unwrap();
+        kvs.store.indexed = true;
+        assert_eq!(
+            block_on(kvs.store.find(&[k])).unwrap(),
+            walked,
+            "Index mismatch for key {:?}",
+            k
+        );
+    }
+}
+
+#[test]
+fn index_overflow() {
+    let mut flash = MockKvs::<2048, 4>::new();
+    flash.set_strict(true);
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 4,
+    };
+    let mut kvs = Kvs::with_index(flash, opts.clone(), Index::<2>::new()).unwrap();
+    let mut buff = [0u8; 16];
+
+    kvs.write(b"a", b"1").unwrap();
+    kvs.write(b"b", b"2").unwrap();
+    assert!(kvs.store.indexed);
+
+    // Once the index is full lookups walk the store
+    kvs.write(b"c", b"3").unwrap();
+    assert!(!kvs.store.indexed);
+    assert_eq!(kvs.read(b"c", &mut buff), Ok(1));
+    assert_eq!(&buff[..1], b"3");
+
+    // Deleting keys frees slots once the index is rebuilt
+    kvs.delete(b"b").unwrap();
+    kvs.delete(b"c").unwrap();
+    for i in 0..100u8 {
+        kvs.write(b"a", &[i; 100]).unwrap();
+    }
+    let mut kvs = Kvs::with_index(kvs.store.flash.0, opts, Index::<2>::new()).unwrap();
+    assert!(kvs.store.indexed);
+    assert_eq!(kvs.read(b"b", &mut buff), Err(Error::NotFound));
+    assert_eq!(kvs.read(b"a", &mut buff), Err(Error::BufferTooSmall));
+}
+
+#[test]
+#[cfg(target_pointer_width = "64")]
+fn index_wide_addresses() {
+    let mut index = Index::<4>::new();
+    let slots = index.slots();
+
+    // Addresses beyond 4 GiB are kept whole rather than truncated, including
+    // those matching the 32-bit sentinels
+    assert!(index::insert(slots, 7, u32::MAX as usize));
+    assert!(index::insert(slots, 7, 1 << 32));
+    let mut p = index::Probe::new(7);
+    assert_eq!(p.next(slots).map(|(_, a)| a), Some(u32::MAX as usize));
+    assert_eq!(p.next(slots).map(|(_, a)| a), Some(1 << 32));
+    assert_eq!(p.next(slots), None);
+}
+
+#[test]
+fn index_reads() {
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 8,
+    };
+    let mut flash = keys_store::<8>(400);
+    flash.set_power_cut(None);
+    let mut kvs = Kvs::with_index(flash, opts, Index::<512>::new()).unwrap();
+
+    // Iteration checks each entry is the latest for its key through the index
+    kvs.store.flash.0.set_power_cut(None);
+    let mut iter = kvs.iter();
+    let mut n = 0;
+    while iter.next().unwrap().is_some() {
+        n += 1;
+    }
+    assert_eq!(n, 400);
+    assert!(kvs.store.flash.0.reads() < 10 * 400);
+
+    // Deleting invalidates only the indexed entry the tombstone supersedes
+    kvs.store.flash.0.set_power_cut(None);
+    kvs.delete(b"k\x00\x01").unwrap();
+    assert!(kvs.store.flash.0.reads() < 20);
+    let mut buff = [0u8; 16];
+    assert_eq!(kvs.read(b"k\x00\x01", &mut buff), Err(Error::NotFound));
+    assert_eq!(kvs.read(b"k\x00\x02", &mut buff), Ok(3));
+}
+
+#[test]
+fn wear_leveling() {
+    let mut kvs = mock_store();
+    let mut erases = [0u32; 4];
+
+    // Formatting erases every page once
+    assert_eq!(kvs.wear(&mut erases), Ok(4));
+    assert_eq!(erases, [1; 4]);
+    assert_eq!(kvs.wear(&mut erases[..3]), Err(Error::BufferTooSmall));
+
+    // A worn free page is skipped until the other pages catch up
+    for i in 0..4 {
+        block_on(kvs.store.erase_page(1, PageKind::Standard, 0, 1 + i)).unwrap();
+    }
+    kvs.write(b"static", b"value").unwrap();
+    for i in 0..200u8 {
+        kvs.write(b"key", &[i; 100]).unwrap();
+
+        kvs.wear(&mut erases).unwrap();
+        let min = erases.iter().min().unwrap();
+        assert!(
+            erases.iter().all(|e| e - min <= 4),
+            "Uneven wear {:?}",
+            erases
+        );
+    }
+    assert!(
+        erases.iter().all(|e| *e >= 5),
+        "Page not rotated {:?}",
+        erases
+    );
+
+    let max = *erases.iter().max().unwrap();
+    let min = *erases.iter().min().unwrap();
+    assert!(max - min <= 1, "Uneven wear {:?}", erases);
+
+    // Counts survive remounting and formatting
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    let mut remounted = [0u32; 4];
+    kvs.wear(&mut remounted).unwrap();
+    assert_eq!(remounted, erases);
+
+    // Formatting erases every page once, and the free page holding the reset
+    // marker once more
+    kvs.format().unwrap();
+    kvs.wear(&mut remounted).unwrap();
+    let mut twice = 0;
+    for (r, e) in remounted.iter().zip(erases.iter()) {
+        match r - e {
+            1 => (),
+            2 => twice += 1,
+            n => panic!("Page erased {} times by format", n),
+        }
+    }
+    assert_eq!(twice, 1);
+}
+
+#[test]
+fn wear_lost_count() {
+    let mut kvs = mock_store();
+    let mut erases = [0u32; 4];
+
+    kvs.write(b"key", b"value").unwrap();
+    block_on(kvs.store.erase_page(2, PageKind::Standard, 0, 2)).unwrap();
+
+    // Counts lost to an interrupted erase are taken as the highest count
+    let addr = kvs.store.page_addr(3);
+    kvs.store.flash.0.erase_page(addr).unwrap();
+    kvs.wear(&mut erases).unwrap();
+    assert_eq!(erases, [1, 1, 3, 3]);
+
+    // So the page is not preferred when rotating
+    for i in 0..20u8 {
+        kvs.write(b"key", &[i; 100]).unwrap();
+    }
+    assert_eq!(kvs.store.page_active, 1);
+}
+
+#[test]
+fn key_too_long() {
+    let mut kvs = mock_store();
+    let key = [0xAA; MAX_KEY_LEN + 1];
+
+    // Keys are checked before reading flash
+    kvs.store.flash.0.set_power_cut(None);
+    assert_eq!(kvs.write(&key, b"value"), Err(Error::KeyTooLong));
+    assert_eq!(kvs.store.flash.0.reads(), 0);
+
+    let mut staging = [0u8; 64];
+    let mut tx = kvs.transaction(&mut staging);
+    assert_eq!(tx.write(&key, b"value"), Err(Error::KeyTooLong));
+
+    // Keys are limited to fit in a page along with their headers
+    let key = [0xAA; 2100];
+    assert_eq!(kvs.write(&key, b"value"), Err(Error::KeyTooLong));
+    let mut buff = [0u8; 8];
+    assert!(matches!(
+        kvs.writer(&key, 5, &mut buff),
+        Err(Error::KeyTooLong)
+    ));
+
+    let mut staging = [0u8; 2200];
+    let mut tx = kvs.transaction(&mut staging);
+    assert_eq!(tx.delete(&key), Err(Error::KeyTooLong));
+
+    // Keys at the limit are split into chunks alongside their value
+    let max = Store::<Blocking<MockKvs<2048, 4>>, NoIndex>::MAX_KEY_LEN;
+    assert_eq!(kvs.write(&key[..max + 1], b"value"), Err(Error::KeyTooLong));
+    kvs.write(&key[..max], b"value").unwrap();
+
+    let mut buff = [0u8; 8];
+    assert_eq!(kvs.read(&key[..max], &mut buff), Ok(5));
+    assert_eq!(&buff[..5], b"value");
+}
+
+/// Fill a value with a pattern that differs between chunks
+fn pattern(value: &mut [u8], seed: u8) {
+    for (i, b) in value.iter_mut().enumerate() {
+        *b = seed.wrapping_add((i / 251) as u8) ^ i as u8;
+    }
+}
+
+#[test]
+fn large_values() {
+    let mut flash = MockKvs::<2048, 8>::new();
+    flash.set_strict(true);
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 8,
+    };
+    let mut kvs = Kvs::new(flash, opts.clone()).unwrap();
+    let (mut a, mut b, mut buff) = ([0u8; 5000], [0u8; 5000], [0u8; 5000]);
+    pattern(&mut a, 1);
+    pattern(&mut b, 2);
+
+    kvs.write(b"small", b"value").unwrap();
+    kvs.write(b"cert", &a).unwrap();
+    assert_eq!(kvs.read(b"cert", &mut buff), Ok(5000));
+    assert_eq!(buff, a);
+    assert_eq!(
+        kvs.read(b"cert", &mut buff[..4999]),
+        Err(Error::BufferTooSmall)
+    );
+
+    // Unchanged values are not rewritten
+    kvs.store.flash.0.set_power_cut(None);
+    kvs.write(b"cert", &a).unwrap();
+    assert_eq!(kvs.store.flash.0.ops(), 0);
+
+    // Rewrites supersede every chunk, through collection and remounting
+    for v in [&b, &a, &b] {
+        kvs.write(b"cert", v).unwrap();
+    }
+    let mut kvs = Kvs::new(kvs.store.flash.0, opts).unwrap();
+    assert_eq!(kvs.read(b"cert", &mut buff), Ok(5000));
+    assert_eq!(buff, b);
+    assert_eq!(kvs.read(b"small", &mut buff), Ok(5));
+
+    // Iteration visits the value once with its full length
+    let (mut iter, mut lens) = (kvs.iter(), [0; 2]);
+    for l in lens.iter_mut() {
+        let mut e = iter.next().unwrap().unwrap();
+        *l = e.value_len();
+        assert_eq!(e.read_value(&mut buff), Ok(*l));
+    }
+    assert!(iter.next().unwrap().is_none());
+    lens.sort();
+    assert_eq!(lens, [5, 5000]);
+
+    // Replacing or deleting the value drops its chunks
+    kvs.write(b"cert", b"short").unwrap();
+    assert_eq!(kvs.read(b"cert", &mut buff), Ok(5));
+    assert!(block_on(kvs.store.live_len()).unwrap() < 100);
+
+    kvs.write(b"cert", &a).unwrap();
+    kvs.delete(b"cert").unwrap();
+    assert_eq!(kvs.read(b"cert", &mut buff), Err(Error::NotFound));
+    assert!(block_on(kvs.store.live_len()).unwrap() < 100);
+
+    // Values larger than the store are refused
+    assert_eq!(kvs.write(b"big", &[0u8; 16000]), Err(Error::Full));
+    assert_eq!(kvs.read(b"small", &mut buff), Ok(5));
+}
+
+#[test]
+fn large_values_indexed() {
+    let mut flash = MockKvs::<2048, 8, 8>::new();
+    flash.set_strict(true);
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 8,
+    };
+    let mut index = Index::<8>::new();
+    let mut kvs = Kvs::with_index(flash, opts.clone(), &mut index).unwrap();
+    let (mut value, mut buff) = ([0u8; 3000], [0u8; 3000]);
+
+    for i in 0..8 {
+        pattern(&mut value, i);
+        kvs.write(b"model", &value).unwrap();
+        kvs.write(b"a", &[i; 100]).unwrap();
+    }
+    assert!(kvs.store.indexed);
+    assert_eq!(kvs.read(b"model", &mut buff), Ok(3000));
+    assert_eq!(buff, value);
+
+    // Chunks are not indexed, so the rebuilt index holds only the two keys
+    let mut kvs = Kvs::with_index(kvs.store.flash.0, opts, &mut index).unwrap();
+    assert!(kvs.store.indexed);
+    assert_eq!(kvs.read(b"model", &mut buff), Ok(3000));
+    assert_eq!(buff, value);
+}
+
+#[test]
+fn read_at() {
+    let mut flash = MockKvs::<2048, 8>::new();
+    flash.set_strict(true);
+    let mut kvs = Kvs::new(
+        flash,
+        Options {
+            start_addr: 0,
+            num_pages: 8,
+        },
+    )
+    .unwrap();
+    let (mut value, mut buff) = ([0u8; 5000], [0u8; 5000]);
+    pattern(&mut value, 3);
+
+    kvs.write(b"small", &value[..100]).unwrap();
+    kvs.write(b"large", &value).unwrap();
+
+    // Parts are read across chunks, stopping at the end of the value
+    for (key, len) in [(&b"small"[..], 100), (&b"large"[..], 5000)] {
+        for (offset, n) in [
+            (0, 10),
+            (90, 20),
+            (100, 10),
+            (2000, 100),
+            (4990, 100),
+            (5000, 10),
+            (6000, 10),
+            (0, 5000),
+        ] {
+            let expected = usize::min(n, len - usize::min(offset, len));
+            assert_eq!(kvs.read_at(key, offset, &mut buff[..n]), Ok(expected));
+            assert_eq!(&buff[..expected], &value[offset.min(len)..][..expected]);
+        }
+    }
+
+    // Values stream through a small buffer
+    let mut offset = 0;
+    loop {
+        let n = kvs.read_at(b"large", offset, &mut buff[..300]).unwrap();
+        if n == 0 {
+            break;
+        }
+        assert_eq!(&buff[..n], &value[offset..][..n]);
+        offset += n;
+    }
+    assert_eq!(offset, 5000);
+
+    kvs.delete(b"large").unwrap();
+    assert_eq!(kvs.read_at(b"large", 0, &mut buff), Err(Error::NotFound));
+    assert_eq!(kvs.read_at(b"missing", 0, &mut buff), Err(Error::NotFound));
+
+    // The CRC is checked over the whole entry, not only the part read
+    let (addr, _) = block_on(kvs.store.find(&[b"small"])).unwrap().unwrap();
+    kvs.store
+        .flash
+        .0
+        .write(addr + EntryHeader::len(1) + 5 + 50, &[0x00])
+        .unwrap();
+    assert_eq!(
+        kvs.read_at(b"small", 0, &mut buff[..10]),
+        Err(Error::Corrupt)
+    );
+}
+
+#[test]
+fn streaming_writes() {
+    let mut flash = MockKvs::<2048, 8, 4>::new();
+    flash.set_strict(true);
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 8,
+    };
+    let mut kvs = Kvs::new(flash, opts.clone()).unwrap();
+    let (mut a, mut b, mut buff) = ([0u8; 5000], [0u8; 5000], [0u8; 5000]);
+    pattern(&mut a, 4);
+    pattern(&mut b, 5);
+    let mut piece = [0u8; 256];
+
+    // Pieces of any size are buffered into chunks
+    let mut w = kvs.writer(b"model", 5000, &mut piece).unwrap();
+    let mut offset = 0;
+    for n in [1, 7, 255, 256, 257, 1000].iter().cycle() {
+        let n = usize::min(*n, 5000 - offset);
+        if n == 0 {
+            break;
+        }
+        w.write(&a[offset..][..n]).unwrap();
+        offset += n;
+    }
+    w.commit().unwrap();
+    assert_eq!(kvs.read(b"model", &mut buff), Ok(5000));
+    assert_eq!(buff, a);
+
+    // Writing more or less than the value length is refused
+    let mut w = kvs.writer(b"model", 10, &mut piece).unwrap();
+    assert_eq!(w.write(&[0; 11]), Err(Error::LengthMismatch));
+    w.write(&[0; 5]).unwrap();
+    assert_eq!(w.commit(), Err(Error::LengthMismatch));
+
+    // Dropped writers leave the previous value in place
+    {
+        let mut w = kvs.writer(b"model", 5000, &mut piece).unwrap();
+        w.write(&b[..3000]).unwrap();
+    }
+    assert_eq!(kvs.read(b"model", &mut buff), Ok(5000));
+    assert_eq!(buff, a);
+
+    // Chunks left by dropped writers are not taken into the next value
+    let mut w = kvs.writer(b"model", 5000, &mut piece[..200]).unwrap();
+    w.write(&b).unwrap();
+    w.commit().unwrap();
+    let mut kvs = Kvs::new(kvs.store.flash.0, opts).unwrap();
+    assert_eq!(kvs.read(b"model", &mut buff), Ok(5000));
+    assert_eq!(buff, b);
+
+    // Writers work through namespaces, for values of any length
+    let mut ns = Namespace::new(&mut kvs, b"ns/");
+    let mut w = ns.writer(b"key", 3, &mut piece).unwrap();
+    w.write(b"abc").unwrap();
+    w.commit().unwrap();
+    assert_eq!(kvs.read(b"ns/key", &mut buff), Ok(3));
+    assert_eq!(&buff[..3], b"abc");
+
+    assert!(matches!(
+        kvs.writer(b"big", 20000, &mut piece),
+        Err(Error::Full)
+    ));
+    assert!(matches!(
+        kvs.writer(b"key", 10, &mut []),
+        Err(Error::BufferTooSmall)
+    ));
+}
+
+#[test]
+fn unsupported_version() {
+    let mut kvs = mock_store();
+    kvs.write(b"key", b"value").unwrap();
+
+    // Rewrite the active page header with a newer format version
+    block_on(kvs.store.flash.erase_page(0)).unwrap();
+    let h = PageHeader {
+        version: PageHeader::VERSION + 1,
+        kind: PageKind::Standard,
+        index: 0,
+        erases: 1,
+        flags: PageFlags::DEFAULT,
+    };
+    block_on(kvs.store.set_page_header(0, h)).unwrap();
+    block_on(kvs.store.clear_page_flag(0, PageFlags::INACTIVE)).unwrap();
+
+    // Mounting is refused rather than reformatting the store
+    let flash = kvs.store.flash.0;
+    let opts = kvs.store.opts;
+    match Kvs::new(flash, opts) {
+        Err(e) => assert_eq!(e, Error::UnsupportedVersion(PageHeader::VERSION + 1)),
+        Ok(_) => panic!("Mounted unsupported version"),
+    }
+}
+
+#[test]
+fn unsupported_older_version() {
+    let mut kvs = mock_store();
+    kvs.write(b"key", b"value").unwrap();
+
+    // Rewrite the active page header with a version that has no migration
+    block_on(kvs.store.flash.erase_page(0)).unwrap();
+    let h = PageHeader {
+        version: 0,
+        kind: PageKind::Standard,
+        index: 0,
+        erases: 1,
+        flags: PageFlags::DEFAULT,
+    };
+    block_on(kvs.store.set_page_header(0, h)).unwrap();
+    block_on(kvs.store.clear_page_flag(0, PageFlags::INACTIVE)).unwrap();
+
+    let flash = kvs.store.flash.0;
+    let opts = kvs.store.opts;
+    match Kvs::new(flash, opts) {
+        Err(e) => assert_eq!(e, Error::UnsupportedVersion(0)),
+        Ok(_) => panic!("Mounted unsupported version"),
+    }
+}
+
+#[test]
+fn unsupported_old_store() {
+    let mut kvs = mock_store();
+    kvs.write(b"key", b"value").unwrap();
+
+    // Rewrite every page header with a version that has no migration, so
+    // that no page is free as if collection had been interrupted
+    for i in 0..kvs.store.opts.num_pages {
+        let addr = kvs.store.page_addr(i);
+        block_on(kvs.store.flash.erase_page(addr)).unwrap();
+        let h = PageHeader {
+            version: 0,
+            kind: PageKind::Standard,
+            index: i as u32,
+            erases: 1,
+            flags: PageFlags::DEFAULT,
+        };
+        block_on(kvs.store.set_page_header(addr, h)).unwrap();
+        block_on(kvs.store.clear_page_flag(addr, PageFlags::INACTIVE)).unwrap();
+    }
+    kvs.store.flash.0.set_power_cut(None);
+
+    // The store is refused before recovery discards the active page
+    let mut s = Store {
+        flash: kvs.store.flash,
+        opts: kvs.store.opts,
+        page_active: 0,
+        page_offset: 0,
+        page_index: 0,
+        index: NoIndex,
+        indexed: false,
+    };
+    assert_eq!(block_on(s.init()), Err(Error::UnsupportedVersion(0)));
+    assert_eq!(s.flash.0.ops(), 0);
+}
+
+/// Write an active version 3 page header, which has no erase count,
+/// returning the address of the first entry
+fn write_v3_page<const W: usize>(
+    flash: &mut MockKvs<2048, 4, W>,
+    page: usize,
+    index: u32,
+) -> usize {
+    let addr = page * 2048;
+    let mut buff = [0xFFu8; 16];
+    buff[0] = 3;
+    buff[1] = PageKind::Standard as u8;
+    buff[2..6].copy_from_slice(&index.to_le_bytes());
+    let crc = crc::crc32::checksum_ieee(&buff[..6]);
+    buff[6..10].copy_from_slice(&crc.to_le_bytes());
+
+    let fields = align(10, W);
+    flash.write(addr, &buff[..fields]).unwrap();
+    flash.write(addr + fields, &[0u8; W]).unwrap();
+
+    addr + fields + 2 * W
+}
+
+/// Write an entry to a version 3 page, returning the address following it
+fn write_v3_entry<const W: usize>(
+    flash: &mut MockKvs<2048, 4, W>,
+    addr: usize,
+    flags: EntryFlags,
+    key: &[u8],
+    value: &[u8],
+) -> usize {
+    let h = EntryHeader {
+        index: 0,
+        kind: EntryKind::Data,
+        flags,
+        key_len: key.len() as u16,
+        val_len: value.len() as u16,
+        crc: data_crc(&[key], value),
+    };
+
+    let mut fields = [0u8; EntryHeader::LEN];
+    h.encode(&mut fields);
+    let mut buff = [0xFFu8; 64];
+    buff[..EntryHeader::LEN].copy_from_slice(&fields);
+    flash
+        .write(addr, &buff[..align(EntryHeader::LEN, W)])
+        .unwrap();
+
+    let mut buff = [0xFFu8; 64];
+    buff[..key.len()].copy_from_slice(key);
+    buff[key.len()..][..value.len()].copy_from_slice(value);
+    flash
+        .write(
+            addr + EntryHeader::len(W),
+            &buff[..align(key.len() + value.len(), W)],
+        )
+        .unwrap();
+
+    for f in [EntryFlags::INACTIVE, EntryFlags::VALID] {
+        if !flags.contains(f) {
+            flash
+                .write(addr + EntryHeader::flag_offset(f, W), &[0u8; W])
+                .unwrap();
+        }
+    }
+
+    addr + h.entry_len(W)
+}
+
+/// Build a version 3 store over two pages, with one key updated on the
+/// newer page
+fn v3_store<const W: usize>() -> MockKvs<2048, 4, W> {
+    let mut flash = MockKvs::<2048, 4, W>::new();
+    flash.set_strict(true);
+
+    let addr = write_v3_page(&mut flash, 0, 0);
+    let addr = write_v3_entry(&mut flash, addr, EntryFlags::empty(), b"a", b"one");
+    write_v3_entry(&mut flash, addr, EntryFlags::VALID, b"b", b"two");
+
+    let addr = write_v3_page(&mut flash, 1, 1);
+    write_v3_entry(&mut flash, addr, EntryFlags::VALID, b"a", b"three");
+
+    flash
+}
+
+/// Check a migrated store holds the version 3 data, with every in-use page
+/// at the current version
+fn check_migrated<const W: usize>(kvs: &mut Kvs<MockKvs<2048, 4, W>>) {
+    let mut buff = [0u8; 16];
+    assert_eq!(kvs.read(b"a", &mut buff), Ok(5));
+    assert_eq!(&buff[..5], b"three");
+    assert_eq!(kvs.read(b"b", &mut buff), Ok(3));
+    assert_eq!(&buff[..3], b"two");
+
+    for i in 0..4 {
+        if let Some(h) = block_on(kvs.store.get_live_page(i)).unwrap() {
+            assert_eq!(h.version, PageHeader::VERSION);
+        }
+    }
+}
+
+fn migrate_v3<const W: usize>() {
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 4,
+    };
+    let mut kvs = Kvs::new(v3_store::<W>(), opts.clone()).unwrap();
+    check_migrated(&mut kvs);
+
+    // The migrated store remains writable and mounts without migrating
+    kvs.write(b"c", b"four").unwrap();
+    let mut kvs = Kvs::new(kvs.store.flash.0, opts).unwrap();
+    check_migrated(&mut kvs);
+    let mut buff = [0u8; 16];
+    assert_eq!(kvs.read(b"c", &mut buff), Ok(4));
+}
+
+#[test]
+fn migrate_version_3() {
+    migrate_v3::<1>();
+    migrate_v3::<4>();
+}
+
+#[test]
+fn power_cut_migration() {
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 4,
+    };
+    let store = |flash| Store {
+        flash: Blocking(flash),
+        opts: opts.clone(),
+        page_active: 0,
+        page_offset: 0,
+        page_index: 0,
+        index: NoIndex,
+        indexed: false,
+    };
+
+    // Count the operations and bytes used by the migration
+    let mut s = store(v3_store::<1>());
+    s.flash.0.set_power_cut(None);
+    block_on(s.init()).unwrap();
+    let (ops, bytes) = (s.flash.0.ops(), s.flash.0.bytes());
+
+    // Cut power at every point, then check the migration resumes on remount
+    let cuts = (0..ops)
+        .map(PowerCut::Ops)
+        .chain((0..bytes).map(PowerCut::Bytes));
+    for cut in cuts {
+        let mut s = store(v3_store::<1>());
+        s.flash.0.set_power_cut(Some(cut));
+        match block_on(s.init()) {
+            Err(Error::Flash(MockError::PowerLoss)) => (),
+            r => panic!("Unexpected result {:?} at cut {:?}", r.map(|_| ()), cut),
+        }
+
+        s.flash.0.set_power_cut(None);
+        let mut kvs = Kvs::new(s.flash.0, opts.clone())
+            .unwrap_or_else(|e| panic!("Mount failed {:?} at cut {:?}", e, cut));
+        check_migrated(&mut kvs);
+    }
+}
+
+/// Store with keys spread over several pages, to be formatted
+fn format_store() -> MockStore {
+    let mut kvs = mock_store();
+    for i in 0..12u8 {
+        kvs.write(&[i], &[i; 300]).unwrap();
+    }
+    kvs
+}
+
+#[test]
+fn power_cut_format() {
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 4,
+    };
+
+    // Count the operations and bytes used by the format
+    let mut kvs = format_store();
+    kvs.store.flash.0.set_power_cut(None);
+    kvs.format().unwrap();
+    let (ops, bytes) = (kvs.store.flash.0.ops(), kvs.store.flash.0.bytes());
+
+    // Cut power at every point, then check the store is either untouched or
+    // wiped on remount
+    let cuts = (0..ops)
+        .map(PowerCut::Ops)
+        .chain((0..bytes).map(PowerCut::Bytes));
+    for cut in cuts {
+        let mut kvs = format_store();
+        kvs.store.flash.0.set_power_cut(Some(cut));
+        assert_eq!(
+            kvs.format(),
+            Err(Error::Flash(MockError::PowerLoss)),
+            "Power not cut at {:?}",
+            cut
+        );
+
+        kvs.store.flash.0.set_power_cut(None);
+        let mut kvs = Kvs::new(kvs.store.flash.0, opts.clone())
+            .unwrap_or_else(|e| panic!("Mount failed {:?} at cut {:?}", e, cut));
+
+        let mut buff = [0u8; 300];
+        let found = (0..12u8)
+            .filter(|i| kvs.read(&[*i], &mut buff) == Ok(300) && buff == [*i; 300])
+            .count();
+        assert!(
+            found == 0 || found == 12,
+            "Found {} keys at cut {:?}",
+            found,
+            cut
+        );
+
+        // The store remains writable
+        kvs.write(b"after", b"cut")
+            .unwrap_or_else(|e| panic!("Write failed {:?} at cut {:?}", e, cut));
+        let mut kvs = Kvs::new(kvs.store.flash.0, opts.clone()).unwrap();
+        assert_eq!(kvs.read(b"after", &mut buff), Ok(3));
+    }
+}
+
+/// Write to the store, deleting the key if no value is provided
+fn apply<F: Flash>(
+    kvs: &mut Kvs<F>,
+    key: &[u8],
+    value: Option<&[u8]>,
+) -> Result<(), Error<F::Error>> {
+    match value {
+        Some(v) => kvs.write(key, v),
+        None => kvs.delete(key),
+    }
+}
+
+/// Fetch the value of a key after a number of writes have been applied
+fn expected<'a>(writes: &[(&[u8], Option<&'a [u8]>)], key: &[u8], n: usize) -> Option<&'a [u8]> {
+    writes[..n]
+        .iter()
+        .rev()
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, v)| *v)
+}
+
+/// Cut power at every point during a sequence of writes and deletes,
+/// checking that each key reads back with its value from either before or
+/// after the interrupted write once the store is remounted
+fn power_cut_harness<const W: usize>(opts: Options, writes: &[(&[u8], Option<&[u8]>)]) {
+    let mock = || {
+        let mut flash = MockKvs::<2048, 2, W>::new();
+        flash.set_strict(true);
+        flash
+    };
+
+    // Count the operations and bytes used by the full sequence
+    let mut kvs = Kvs::new(mock(), opts.clone()).unwrap();
+    kvs.store.flash.0.set_power_cut(None);
+    for (k, v) in writes {
+        apply(&mut kvs, k, *v).unwrap();
+    }
+    let (ops, bytes) = (kvs.store.flash.0.ops(), kvs.store.flash.0.bytes());
+
+    let cuts = (0..ops)
+        .map(PowerCut::Ops)
+        .chain((0..bytes).map(PowerCut::Bytes));
+    for cut in cuts {
+        let mut kvs = Kvs::new(mock(), opts.clone()).unwrap();
+        kvs.store.flash.0.set_power_cut(Some(cut));
+
+        let mut done = 0;
+        for (k, v) in writes {
+            match apply(&mut kvs, k, *v) {
+                Ok(()) => done += 1,
+                Err(Error::Flash(MockError::PowerLoss)) => break,
+                Err(e) => panic!("Unexpected error {:?} at cut {:?}", e, cut),
+            }
+        }
+        assert!(!kvs.store.flash.0.powered(), "Power not cut at {:?}", cut);
+
+        // Remount and check each key is in the old or new state
+        kvs.store.flash.0.set_power_cut(None);
+        let mut kvs = Kvs::new(kvs.store.flash.0, opts.clone())
+            .unwrap_or_else(|e| panic!("Mount failed {:?} at cut {:?}", e, cut));
+        let mut buff = [0u8; 64];
+
+        for (k, _) in writes {
+            let v = match kvs.read(k, &mut buff) {
+                Ok(n) => Some(&buff[..n]),
+                Err(Error::NotFound) => None,
+                Err(e) => panic!("Unexpected error {:?} at cut {:?}", e, cut),
+            };
+
+            let new = match writes[done].0 == *k {
+                true => Some(writes[done].1),
+                false => None,
+            };
+
+            assert!(
+                v == expected(writes, k, done) || new == Some(v),
+                "Unexpected value for key {:?} at cut {:?}",
+                k,
+                cut
+            );
+        }
+
+        // The recovered store remains writable
+        kvs.write(b"after", b"cut")
+            .unwrap_or_else(|e| panic!("Write failed {:?} at cut {:?}", e, cut));
+        let mut kvs = Kvs::new(kvs.store.flash.0, opts.clone()).unwrap();
+        assert_eq!(kvs.read(b"after", &mut buff), Ok(3));
+    }
+}
+
+/// Interleaved writes over a few keys, enough for the two page store to
+/// collect each page
+fn power_cut_writes_seq() -> [(&'static [u8], Option<&'static [u8]>); 48] {
+    const VALUES: [[u8; 60]; 4] = [[0x11; 60], [0x22; 60], [0x33; 60], [0x44; 60]];
+    let keys: [&[u8]; 3] = [b"a", b"bb", b"ccc"];
+
+    let mut writes = [(&b""[..], None); 48];
+    for (i, w) in writes.iter_mut().enumerate() {
+        *w = (keys[i % keys.len()], Some(&VALUES[i % VALUES.len()][..]));
+    }
+    writes
+}
+
+#[test]
+fn power_cut_writes() {
+    power_cut_harness::<1>(
+        Options {
+            start_addr: 0,
+            num_pages: 2,
+        },
+        &power_cut_writes_seq(),
+    );
+}
+
+#[test]
+fn power_cut_writes_aligned() {
+    power_cut_harness::<8>(
+        Options {
+            start_addr: 0,
+            num_pages: 2,
+        },
+        &power_cut_writes_seq(),
+    );
+}
+
+#[test]
+fn power_cut_deletes() {
+    // Delete each key in turn, with some deletes repeated
+    let mut writes = power_cut_writes_seq();
+    for w in writes.iter_mut().skip(3).step_by(4) {
+        w.1 = None;
+    }
+
+    power_cut_harness::<1>(
+        Options {
+            start_addr: 0,
+            num_pages: 2,
+        },
+        &writes,
+    );
+}
+
+#[test]
+fn power_cut_transactions() {
+    const VALUES: [[u8; 60]; 4] = [[0x11; 60], [0x22; 60], [0x33; 60], [0x44; 60]];
+    const KEYS: [&[u8]; 3] = [b"a", b"bb", b"ccc"];
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 2,
+    };
+
+    // Each transaction writes the same value to every key
+    let commit = |kvs: &mut Kvs<MockKvs<2048, 2>>, i: usize| {
+        let mut staging = [0u8; 256];
+        let mut tx = kvs.transaction(&mut staging);
+        for k in KEYS {
+            tx.write(k, &VALUES[i % VALUES.len()]).unwrap();
+        }
+        tx.commit()
+    };
+    let mock = || {
+        let mut flash = MockKvs::<2048, 2>::new();
+        flash.set_strict(true);
+        flash
+    };
+
+    let mut kvs = Kvs::new(mock(), opts.clone()).unwrap();
+    kvs.store.flash.0.set_power_cut(None);
+    for i in 0..12 {
+        commit(&mut kvs, i).unwrap();
+    }
+    let (ops, bytes) = (kvs.store.flash.0.ops(), kvs.store.flash.0.bytes());
+
+    let cuts = (0..ops)
+        .map(PowerCut::Ops)
+        .chain((0..bytes).map(PowerCut::Bytes));
+    for cut in cuts {
+        let mut kvs = Kvs::new(mock(), opts.clone()).unwrap();
+        kvs.store.flash.0.set_power_cut(Some(cut));
+
+        let mut done: usize = 0;
+        for i in 0..12 {
+            match commit(&mut kvs, i) {
+                Ok(()) => done += 1,
+                Err(Error::Flash(MockError::PowerLoss)) => break,
+                Err(e) => panic!("Unexpected error {:?} at cut {:?}", e, cut),
+            }
+        }
+        assert!(!kvs.store.flash.0.powered(), "Power not cut at {:?}", cut);
+
+        // Remount and check all keys hold the value of the same transaction
+        kvs.store.flash.0.set_power_cut(None);
+        let mut kvs = Kvs::new(kvs.store.flash.0, opts.clone())
+            .unwrap_or_else(|e| panic!("Mount failed {:?} at cut {:?}", e, cut));
+
+        let mut values = KEYS.iter().map(|k| {
+            let mut v = [0u8; 60];
+            match kvs.read(k, &mut v) {
+                Ok(60) => Some(v),
+                Err(Error::NotFound) => None,
+                r => panic!("Unexpected result {:?} at cut {:?}", r, cut),
+            }
+        });
+        let v = values.next().unwrap();
+        assert!(
+            values.all(|w| w == v),
+            "Partial transaction at cut {:?}",
+            cut
+        );
+
+        let old = done.checked_sub(1).map(|i| VALUES[i % VALUES.len()]);
+        let new = Some(VALUES[done % VALUES.len()]);
+        assert!(v == old || v == new, "Unexpected value at cut {:?}", cut);
+    }
+}
+
+#[test]
+fn power_cut_large_values() {
+    const LEN: usize = 600;
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 8,
+    };
+
+    // Alternate writes of a value spanning pages with small writes
+    let write = |kvs: &mut Kvs<MockKvs<256, 8>>, i: usize| {
+        let mut value = [0u8; LEN];
+        pattern(&mut value, i as u8);
+        match i % 3 {
+            2 => kvs.write(b"a", &[i as u8; 40]),
+            _ => kvs.write(b"blob", &value),
+        }
+    };
+    let mock = || {
+        let mut flash = MockKvs::<256, 8>::new();
+        flash.set_strict(true);
+        flash
+    };
+
+    let mut kvs = Kvs::new(mock(), opts.clone()).unwrap();
+    kvs.store.flash.0.set_power_cut(None);
+    for i in 0..9 {
+        write(&mut kvs, i).unwrap();
+    }
+    let (ops, bytes) = (kvs.store.flash.0.ops(), kvs.store.flash.0.bytes());
+
+    let cuts = (0..ops)
+        .map(PowerCut::Ops)
+        .chain((0..bytes).map(PowerCut::Bytes));
+    for cut in cuts {
+        let mut kvs = Kvs::new(mock(), opts.clone()).unwrap();
+        kvs.store.flash.0.set_power_cut(Some(cut));
+
+        let mut done: usize = 0;
+        for i in 0..9 {
+            match write(&mut kvs, i) {
+                Ok(()) => done += 1,
+                Err(Error::Flash(MockError::PowerLoss)) => break,
+                Err(e) => panic!("Unexpected error {:?} at cut {:?}", e, cut),
+            }
+        }
+        assert!(!kvs.store.flash.0.powered(), "Power not cut at {:?}", cut);
+
+        // Remount and check the value is whole, from before or after the cut
+        kvs.store.flash.0.set_power_cut(None);
+        let mut kvs = Kvs::new(kvs.store.flash.0, opts.clone())
+            .unwrap_or_else(|e| panic!("Mount failed {:?} at cut {:?}", e, cut));
+
+        let mut v = [0u8; LEN];
+        let v = match kvs.read(b"blob", &mut v) {
+            Ok(LEN) => Some(v),
+            Err(Error::NotFound) => None,
+            r => panic!("Unexpected result {:?} at cut {:?}", r, cut),
+        };
+
+        let expected = |i: Option<usize>| {
+            i.map(|i| {
+                let mut value = [0u8; LEN];
+                pattern(&mut value, i as u8);
+                value
+            })
+        };
+        let old = expected((0..done).rev().find(|i| i % 3 != 2));
+        let new = expected(Some(done).filter(|i| i % 3 != 2));
+        assert!(
+            v == old || (new.is_some() && v == new),
+            "Unexpected value at cut {:?}",
+            cut
+        );
+
+        // The recovered store holds no orphaned chunks and remains writable
+        assert!(
+            block_on(kvs.store.live_len()).unwrap() < 2 * LEN,
+            "Chunks leaked at cut {:?}",
+            cut
+        );
+        write(&mut kvs, 0).unwrap_or_else(|e| panic!("Write failed {:?} at cut {:?}", e, cut));
+    }
+}
+
+#[test]
+fn mock_strict_writes() {
+    let mut flash = MockKvs::<2048, 2>::new();
+    flash.set_strict(true);
+
+    // Bits may be cleared progressively
+    flash.write(0, &[0xF0, 0xFF]).unwrap();
+    flash.write(0, &[0xC0, 0xFF]).unwrap();
+    assert_eq!(&flash.data()[..2], &[0xC0, 0xFF]);
+
+    // But not set, or re-programmed without change
+    assert_eq!(flash.write(0, &[0xFF, 0x00]), Err(MockError::BitSet(0)));
+    assert_eq!(flash.write(0, &[0xC0]), Err(MockError::DoubleWrite(0)));
+    assert_eq!(flash.write(1, &[0x00, 0x80]), Ok(()));
+    assert_eq!(
+        flash.write(0, &[0x80, 0x00]),
+        Err(MockError::DoubleWrite(1))
+    );
+    assert_eq!(&flash.data()[..3], &[0xC0, 0x00, 0x80]);
+
+    // Or cross pages
+    assert_eq!(
+        flash.write(2047, &[0x00, 0x00]),
+        Err(MockError::PageBoundary(2047))
+    );
+    flash.write(2047, &[0x00]).unwrap();
+
+    // Erasing allows programming again
+    flash.erase_page(0).unwrap();
+    flash.write(0, &[0x00, 0x00]).unwrap();
+
+    // Lenient writes copy data
+    flash.set_strict(false);
+    flash.write(0, &[0xAA, 0x55]).unwrap();
+    assert_eq!(&flash.data()[..2], &[0xAA, 0x55]);
+}
+
+#[test]
+fn mock_strict_word_writes() {
+    let mut flash = MockKvs::<2048, 2, 4>::new();
+    flash.set_strict(true);
+
+    // Writes must be aligned to words
+    assert_eq!(flash.write(2, &[0x00; 4]), Err(MockError::Unaligned(2)));
+    assert_eq!(flash.write(0, &[0x00; 3]), Err(MockError::Unaligned(0)));
+    flash.write(0, &[0xF0, 0xFF, 0xFF, 0xFF]).unwrap();
+
+    // And each word programmed only once
+    assert_eq!(flash.write(0, &[0x00; 8]), Err(MockError::DoubleWrite(0)));
+    assert_eq!(
+        flash.write(0, &[0x00, 0xFF, 0xFF, 0xFF]),
+        Err(MockError::DoubleWrite(0))
+    );
+    flash.write(4, &[0x00; 4]).unwrap();
+    assert_eq!(
+        &flash.data()[..8],
+        &[0xF0, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00]
+    );
+
+    flash.erase_page(0).unwrap();
+    flash.write(0, &[0x00; 8]).unwrap();
+}
+
+#[test]
+fn write_read_aligned() {
+    let mut flash = MockKvs::<2048, 2, 8>::new();
+    flash.set_strict(true);
+    let mut kvs = Kvs::new(
+        flash,
+        Options {
+            start_addr: 0,
+            num_pages: 2,
+        },
+    )
+    .unwrap();
+    let mut buff = [0u8; 16];
+
+    // Entries are padded to words, with flags updated without re-programming
+    kvs.write(b"key", b"value").unwrap();
+    assert_eq!(
+        kvs.store.page_offset as usize,
+        PageHeader::len(8) + EntryHeader::len(8) + 8
+    );
+    for i in 0..100u8 {
+        kvs.write(b"key", &[i; 13]).unwrap();
+    }
+
+    let mut kvs = Kvs::new(kvs.store.flash.0, kvs.store.opts).unwrap();
+    assert_eq!(kvs.read(b"key", &mut buff), Ok(13));
+    assert_eq!(&buff[..13], &[99; 13]);
+}
+
+/// Async flash backed by the mock, yielding once before each operation
+struct YieldingFlash(MockKvs<2048, 2, 4>);
+
+/// Future returning pending on first poll
+struct YieldNow(bool);
+
+impl core::future::Future for YieldNow {
+    type Output = ();
+
+    fn poll(
+        mut self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<()> {
+        if self.0 {
+            return core::task::Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        core::task::Poll::Pending
+    }
+}
+
+impl AsyncFlash for YieldingFlash {
+    const PAGE_SIZE: usize = 2048;
+
+    const WRITE_SIZE: usize = 4;
+
+    type Error = MockError;
+
+    async fn read(&mut self, addr: usize, data: &mut [u8]) -> Result<(), Self::Error> {
+        YieldNow(false).await;
+        Flash::read(&mut self.0, addr, data)
+    }
+
+    async fn write(&mut self, addr: usize, data: &[u8]) -> Result<(), Self::Error> {
+        YieldNow(false).await;
+        Flash::write(&mut self.0, addr, data)
+    }
+
+    async fn erase_page(&mut self, addr: usize) -> Result<(), Self::Error> {
+        YieldNow(false).await;
+        Flash::erase_page(&mut self.0, addr)
+    }
+
+    fn capacity(&self) -> usize {
+        Flash::capacity(&self.0)
+    }
+}
+
+#[test]
+fn async_write_read() {
+    let mut flash = MockKvs::<2048, 2, 4>::new();
+    flash.set_strict(true);
+    let opts = Options {
+        start_addr: 0,
+        num_pages: 2,
+    };
+    let mut buff = [0u8; 16];
+
+    let flash = block_on(async {
+        let mut kvs = AsyncKvs::new(YieldingFlash(flash), opts.clone())
+            .await
+            .unwrap();
+        for i in 0..100u8 {
+            kvs.write(b"key", &[i; 13]).await.unwrap();
+        }
+        kvs.write(b"other", b"value").await.unwrap();
+
+        assert_eq!(kvs.read(b"key", &mut buff).await, Ok(13));
+        assert_eq!(&buff[..13], &[99; 13]);
+
+        // Entries may be iterated asynchronously
+        let mut iter = kvs.iter();
+        let mut e = iter.next().await.unwrap().unwrap();
+        assert_eq!(e.read_key(&mut buff).await, Ok(3));
+        assert_eq!(&buff[..3], b"key");
+        let mut e = iter.next().await.unwrap().unwrap();
+        assert_eq!(e.read_value(&mut buff).await, Ok(5));
+        assert_eq!(&buff[..5], b"value");
+        assert!(iter.next().await.unwrap().is_none());
+
+        kvs.store.flash.0
+    });
+
+    // Stores written asynchronously may be mounted by the blocking API
+    let mut kvs = Kvs::new(flash, opts).unwrap();
+    assert_eq!(kvs.read(b"key", &mut buff), Ok(13));
+    assert_eq!(&buff[..13], &[99; 13]);
+    assert_eq!(kvs.read(b"other", &mut buff), Ok(5));
+    assert_eq!(&buff[..5], b"value");
+}
+
+#[cfg(feature = "embedded-storage")]
+mod nor_flash {
+    use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+
+    use crate::mock::{MockError, MockKvs};
+    use crate::nor_flash::NorFlashAdapter;
+    use crate::*;
+
+    /// NOR flash with word aligned reads and writes, backed by the mock
+    struct MockNor(MockKvs<2048, 2, 8>);
+
+    impl ErrorType for MockNor {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for MockNor {
+        const READ_SIZE: usize = 4;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            if !(offset as usize).is_multiple_of(Self::READ_SIZE)
+                || !bytes.len().is_multiple_of(Self::READ_SIZE)
+            {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            self.0
+                .read(offset as usize, bytes)
+                .map_err(|_| NorFlashErrorKind::Other)
+        }
+
+        fn capacity(&self) -> usize {
+            2048 * 2
+        }
+    }
+
+    impl NorFlash for MockNor {
+        const WRITE_SIZE: usize = 8;
+        const ERASE_SIZE: usize = 2048;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            for addr in (from..to).step_by(Self::ERASE_SIZE) {
+                self.0
+                    .erase_page(addr as usize)
+                    .map_err(|_| NorFlashErrorKind::Other)?;
+            }
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            match self.0.write(offset as usize, bytes) {
+                Ok(()) => Ok(()),
+                Err(MockError::Unaligned(_)) => Err(NorFlashErrorKind::NotAligned),
+                Err(_) => Err(NorFlashErrorKind::Other),
+            }
+        }
+    }
+
+    #[test]
+    fn nor_flash_adapter() {
+        let mut mock = MockKvs::new();
+        mock.set_strict(true);
+        let mut flash = NorFlashAdapter::new(MockNor(mock));
+        let mut buff = [0u8; 16];
+
+        // Unaligned reads are padded to words, while unaligned writes are
+        // rejected rather than programming words twice
+        flash.write(0, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        flash.read(1, &mut buff[..11]).unwrap();
+        assert_eq!(&buff[..11], &[2, 3, 4, 5, 6, 7, 8, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(flash.write(11, &[1]), Err(NorFlashErrorKind::NotAligned));
+        assert_eq!(flash.write(8, &[1]), Err(NorFlashErrorKind::NotAligned));
+
+        let mut kvs = Kvs::new(
+            flash,
+            Options {
+                start_addr: 0,
+                num_pages: 2,
+            },
+        )
+        .unwrap();
+        for i in 0..100u8 {
+            kvs.write(b"key", &[i; 13]).unwrap();
+        }
+        assert_eq!(kvs.read(b"key", &mut buff), Ok(13));
+        assert_eq!(&buff[..13], &[99; 13]);
+    }
+}
+
+#[cfg(feature = "serde")]
+mod typed {
+    use crate::mock::MockKvs;
+    use crate::*;
+
+    #[test]
+    fn typed_values() {
+        let mut kvs = Kvs::new(
+            MockKvs::<2048, 2>::new(),
+            Options {
+                start_addr: 0,
+                num_pages: 2,
+            },
+        )
+        .unwrap();
+        let mut scratch = [0u8; 32];
+
+        kvs.write_typed(b"calibration", &[1u16, 2000, 3, 40000], &mut scratch)
+            .unwrap();
+        assert_eq!(
+            kvs.read_typed::<[u16; 4]>(b"calibration", &mut scratch),
+            Ok([1, 2000, 3, 40000])
+        );
+
+        // Values must fit the scratch buffer
+        assert_eq!(
+            kvs.write_typed(b"large", &[0xFFFF_FFFFu32; 16], &mut scratch),
+            Err(Error::BufferTooSmall)
+        );
+        assert_eq!(
+            kvs.read_typed::<[u16; 4]>(b"calibration", &mut scratch[..1]),
+            Err(Error::BufferTooSmall)
+        );
+
+        // Stored data must decode as the requested type
+        kvs.write(b"raw", &[0xFF]).unwrap();
+        assert_eq!(
+            kvs.read_typed::<u32>(b"raw", &mut scratch),
+            Err(Error::Decode)
+        );
+        assert_eq!(
+            kvs.read_typed::<u32>(b"missing", &mut scratch),
+            Err(Error::NotFound)
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Atomic multi-key transactions
+//!
+//! A [`Transaction`] stages writes and deletes in a caller-provided buffer,
+//! then commits them together so that following a power loss either all or
+//! none of the staged operations are visible. Dropping a transaction without
+//! committing discards the staged operations.
+//!
+//! ```
+//! # #[cfg(feature = "mock")] {
+//! # use fkvs::{Kvs, Options, mock::MockKvs};
+//! # fn setup(kvs: &mut Kvs<MockKvs<2048, 4>>) {
+//! let mut buff = [0u8; 128];
+//! let mut tx = kvs.transaction(&mut buff);
+//! tx.write(b"ssid", b"fkvs").unwrap();
+//! tx.write(b"psk", b"hunter2").unwrap();
+//! tx.delete(b"bssid").unwrap();
+//! tx.commit().unwrap();
+//! # }
+//! # }
+//! ```
+//!
+//! All staged entries must fit within a single page alongside a commit
+//! marker, committing larger transactions returns [`Error::Full`].
+
+use core::fmt::Debug;
+
+use crate::header::EntryKind;
+use crate::{
+    block_on, AsyncFlash, AsyncKvs, Blocking, Error, Flash, KeyIndex, Kvs, NoIndex, Store,
+};
+
+/// Length of the header preceding each staged operation
+///
+/// ```text
+/// 0      1         3         5
+/// | kind | key_len | val_len | key | value |
+/// ```
+const OP_LEN: usize = 5;
+
+/// Transaction staging writes and deletes, created by [`Kvs::transaction`]
+pub struct Transaction<'a, F: Flash, I: KeyIndex = NoIndex> {
+    kvs: &'a mut Kvs<F, I>,
+    ops: Ops<'a>,
+}
+
+impl<'a, F, E, I> Transaction<'a, F, I>
+where
+    F: Flash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    pub(crate) fn new(kvs: &'a mut Kvs<F, I>, buff: &'a mut [u8]) -> Self {
+        Self {
+            kvs,
+            ops: Ops {
+                buff,
+                len: 0,
+                max_key_len: Store::<Blocking<F>, I>::MAX_KEY_LEN,
+            },
+        }
+    }
+
+    /// Stage a write of a value to a key
+    pub fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error<E>> {
+        self.ops.push(EntryKind::Data, key, value)
+    }
+
+    /// Stage deletion of a key
+    pub fn delete(&mut self, key: &[u8]) -> Result<(), Error<E>> {
+        self.ops.push(EntryKind::Tombstone, key, &[])
+    }
+
+    /// Commit all staged operations
+    pub fn commit(self) -> Result<(), Error<E>> {
+        block_on(self.kvs.store.commit(self.ops.as_slice()))
+    }
+}
+
+/// Transaction staging writes and deletes, created by [`AsyncKvs::transaction`],
+/// see [`Transaction`]
+pub struct AsyncTransaction<'a, F: AsyncFlash, I: KeyIndex = NoIndex> {
+    kvs: &'a mut AsyncKvs<F, I>,
+    ops: Ops<'a>,
+}
+
+impl<'a, F, E, I> AsyncTransaction<'a, F, I>
+where
+    F: AsyncFlash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    pub(crate) fn new(kvs: &'a mut AsyncKvs<F, I>, buff: &'a mut [u8]) -> Self {
+        Self {
+            kvs,
+            ops: Ops {
+                buff,
+                len: 0,
+                max_key_len: Store::<F, I>::MAX_KEY_LEN,
+            },
+        }
+    }
+
+    /// Stage a write of a value to a key
+    pub fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error<E>> {
+        self.ops.push(EntryKind::Data, key, value)
+    }
+
+    /// Stage deletion of a key
+    pub fn delete(&mut self, key: &[u8]) -> Result<(), Error<E>> {
+        self.ops.push(EntryKind::Tombstone, key, &[])
+    }
+
+    /// Commit all staged operations
+    pub async fn commit(self) -> Result<(), Error<E>> {
+        self.kvs.store.commit(self.ops.as_slice()).await
+    }
+}
+
+/// Operations staged in a caller-provided buffer
+struct Ops<'a> {
+    buff: &'a mut [u8],
+    len: usize,
+    /// Maximum key length for the store's page size
+    max_key_len: usize,
+}
+
+impl Ops<'_> {
+    /// Append an operation to the buffer
+    fn push<E>(&mut self, kind: EntryKind, key: &[u8], value: &[u8]) -> Result<(), Error<E>> {
+        if key.len() > self.max_key_len {
+            return Err(Error::KeyTooLong);
+        }
+        if value.len() > u16::MAX as usize {
+            return Err(Error::Full);
+        }
+
+        let len = OP_LEN + key.len() + value.len();
+        if self.buff.len() - self.len < len {
+            return Err(Error::BufferTooSmall);
+        }
+
+        let b = &mut self.buff[self.len..][..len];
+        b[0] = kind as u8;
+        b[1..3].copy_from_slice(&(key.len() as u16).to_le_bytes());
+        b[3..5].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        b[OP_LEN..][..key.len()].copy_from_slice(key);
+        b[OP_LEN + key.len()..].copy_from_slice(value);
+        self.len += len;
+
+        Ok(())
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.buff[..self.len]
+    }
+}
+
+/// Decode staged operations, returning the kind, key, and value of each
+pub(crate) fn staged(mut ops: &[u8]) -> impl Iterator<Item = (EntryKind, &[u8], &[u8])> {
+    core::iter::from_fn(move || {
+        if ops.is_empty() {
+            return None;
+        }
+
+        let kind = match ops[0] {
+            0x01 => EntryKind::Tombstone,
+            _ => EntryKind::Data,
+        };
+        let key_len = u16::from_le_bytes([ops[1], ops[2]]) as usize;
+        let val_len = u16::from_le_bytes([ops[3], ops[4]]) as usize;
+
+        let (op, rest) = ops.split_at(OP_LEN + key_len + val_len);
+        ops = rest;
+
+        let (key, value) = op[OP_LEN..].split_at(key_len);
+        Some((kind, key, value))
+    })
+}
@@ -0,0 +1,89 @@
+//! Typed values encoded with [`postcard`]
+//!
+//! Enabled with the `serde` feature, values implementing [`Serialize`] may
+//! be written and read directly. Values are encoded through a caller
+//! provided scratch buffer, which must fit the encoded value, so no
+//! allocation is required.
+
+use core::fmt::Debug;
+
+use log::warn;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{AsyncFlash, AsyncKvs, Error, Flash, KeyIndex, Kvs};
+
+impl<F, E, I> Kvs<F, I>
+where
+    F: Flash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    /// Encode and write a typed value, using `scratch` to hold the encoded value
+    pub fn write_typed<T: Serialize>(
+        &mut self,
+        key: &[u8],
+        value: &T,
+        scratch: &mut [u8],
+    ) -> Result<(), Error<E>> {
+        let data = encode(value, scratch)?;
+        self.write(key, data)
+    }
+
+    /// Read and decode a typed value, using `scratch` to hold the encoded value
+    pub fn read_typed<T: DeserializeOwned>(
+        &mut self,
+        key: &[u8],
+        scratch: &mut [u8],
+    ) -> Result<T, Error<E>> {
+        let n = self.read(key, scratch)?;
+        decode(&scratch[..n])
+    }
+}
+
+impl<F, E, I> AsyncKvs<F, I>
+where
+    F: AsyncFlash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    /// Encode and write a typed value, see [`Kvs::write_typed`]
+    pub async fn write_typed<T: Serialize>(
+        &mut self,
+        key: &[u8],
+        value: &T,
+        scratch: &mut [u8],
+    ) -> Result<(), Error<E>> {
+        let data = encode(value, scratch)?;
+        self.write(key, data).await
+    }
+
+    /// Read and decode a typed value, see [`Kvs::read_typed`]
+    pub async fn read_typed<T: DeserializeOwned>(
+        &mut self,
+        key: &[u8],
+        scratch: &mut [u8],
+    ) -> Result<T, Error<E>> {
+        let n = self.read(key, scratch).await?;
+        decode(&scratch[..n])
+    }
+}
+
+/// Encode a value into the scratch buffer, returning the encoded data
+fn encode<'a, T: Serialize, E>(value: &T, scratch: &'a mut [u8]) -> Result<&'a [u8], Error<E>> {
+    match postcard::to_slice(value, scratch) {
+        Ok(data) => Ok(data),
+        Err(postcard::Error::SerializeBufferFull) => Err(Error::BufferTooSmall),
+        Err(e) => {
+            warn!("FKVS failed to encode value: {:?}", e);
+            Err(Error::Encode)
+        }
+    }
+}
+
+/// Decode a value from stored data
+fn decode<T: DeserializeOwned, E>(data: &[u8]) -> Result<T, Error<E>> {
+    postcard::from_bytes(data).map_err(|e| {
+        warn!("FKVS failed to decode value: {:?}", e);
+        Error::Decode
+    })
+}
@@ -0,0 +1,189 @@
+//! Streaming writes of values in pieces
+//!
+//! A [`Writer`] accepts a value of a known length in pieces, so values larger
+//! than the available RAM can be written through a small caller-provided
+//! buffer. Pieces are buffered then written to flash as chunks of the value,
+//! which is committed once every byte has been written.
+//!
+//! ```
+//! # #[cfg(feature = "mock")] {
+//! # use fkvs::{Kvs, Options, mock::MockKvs};
+//! # fn setup(kvs: &mut Kvs<MockKvs<2048, 4>>, blocks: &[[u8; 64]]) {
+//! let mut buff = [0u8; 256];
+//! let mut w = kvs.writer(b"cert", blocks.len() * 64, &mut buff).unwrap();
+//! for b in blocks {
+//!     w.write(b).unwrap();
+//! }
+//! w.commit().unwrap();
+//! # }
+//! # }
+//! ```
+//!
+//! Space for the whole value is reserved when the writer is created, and the
+//! value is only visible once committed. Dropping a writer without committing
+//! discards the value, keeping any previous value for the key.
+//!
+//! Values may be read back in parts with [`Kvs::read_at`].
+
+use core::fmt::Debug;
+
+use crate::blob::Blob;
+use crate::{block_on, AsyncFlash, AsyncKvs, Error, Flash, KeyIndex, Kvs, NoIndex, Store};
+
+/// Writer accepting a value in pieces, created by [`Kvs::writer`]
+pub struct Writer<'a, F: Flash, I: KeyIndex = NoIndex> {
+    kvs: &'a mut Kvs<F, I>,
+    pieces: Pieces<'a>,
+}
+
+impl<'a, F, E, I> Writer<'a, F, I>
+where
+    F: Flash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    pub(crate) fn new(
+        kvs: &'a mut Kvs<F, I>,
+        key: [&'a [u8]; 2],
+        len: usize,
+        buff: &'a mut [u8],
+    ) -> Result<Self, Error<E>> {
+        let pieces = block_on(Pieces::new(&mut kvs.store, key, len, buff))?;
+        Ok(Self { kvs, pieces })
+    }
+
+    /// Write the next part of the value
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error<E>> {
+        block_on(self.pieces.write(&mut self.kvs.store, data))
+    }
+
+    /// Commit the value, once every byte has been written
+    pub fn commit(self) -> Result<(), Error<E>> {
+        block_on(self.pieces.commit(&mut self.kvs.store))
+    }
+}
+
+/// Writer accepting a value in pieces, created by [`AsyncKvs::writer`], see
+/// [`Writer`]
+pub struct AsyncWriter<'a, F: AsyncFlash, I: KeyIndex = NoIndex> {
+    kvs: &'a mut AsyncKvs<F, I>,
+    pieces: Pieces<'a>,
+}
+
+impl<'a, F, E, I> AsyncWriter<'a, F, I>
+where
+    F: AsyncFlash<Error = E>,
+    E: Debug,
+    I: KeyIndex,
+{
+    pub(crate) async fn new(
+        kvs: &'a mut AsyncKvs<F, I>,
+        key: [&'a [u8]; 2],
+        len: usize,
+        buff: &'a mut [u8],
+    ) -> Result<Self, Error<E>> {
+        let pieces = Pieces::new(&mut kvs.store, key, len, buff).await?;
+        Ok(Self { kvs, pieces })
+    }
+
+    /// Write the next part of the value
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), Error<E>> {
+        self.pieces.write(&mut self.kvs.store, data).await
+    }
+
+    /// Commit the value, once every byte has been written
+    pub async fn commit(self) -> Result<(), Error<E>> {
+        self.pieces.commit(&mut self.kvs.store).await
+    }
+}
+
+/// Value written in pieces through a caller-provided buffer, each full
+/// buffer written as a piece of the value
+struct Pieces<'a> {
+    key: [&'a [u8]; 2],
+    buff: &'a mut [u8],
+    /// Length of data held in the buffer
+    len: usize,
+    blob: Blob,
+}
+
+impl<'a> Pieces<'a> {
+    async fn new<F, E, I>(
+        store: &mut Store<F, I>,
+        key: [&'a [u8]; 2],
+        len: usize,
+        buff: &'a mut [u8],
+    ) -> Result<Self, Error<E>>
+    where
+        F: AsyncFlash<Error = E>,
+        E: Debug,
+        I: KeyIndex,
+    {
+        let existing = store.find(&key).await?.map(|(_, h)| h);
+        let blob = store.begin_blob(&key, len, buff.len(), existing).await?;
+
+        Ok(Self {
+            key,
+            buff,
+            len: 0,
+            blob,
+        })
+    }
+
+    /// Append data to the buffer, writing a piece each time it fills
+    async fn write<F, E, I>(
+        &mut self,
+        store: &mut Store<F, I>,
+        mut data: &[u8],
+    ) -> Result<(), Error<E>>
+    where
+        F: AsyncFlash<Error = E>,
+        E: Debug,
+        I: KeyIndex,
+    {
+        if self.len + data.len() > self.blob.remaining() {
+            return Err(Error::LengthMismatch);
+        }
+
+        while !data.is_empty() {
+            // Whole pieces are written without copying to the buffer
+            if self.len == 0 && data.len() >= self.buff.len() {
+                let (piece, rest) = data.split_at(self.buff.len());
+                store.write_chunks(&self.key, &mut self.blob, piece).await?;
+                data = rest;
+                continue;
+            }
+
+            let n = usize::min(self.buff.len() - self.len, data.len());
+            self.buff[self.len..][..n].copy_from_slice(&data[..n]);
+            self.len += n;
+            data = &data[n..];
+
+            if self.len == self.buff.len() {
+                store
+                    .write_chunks(&self.key, &mut self.blob, self.buff)
+                    .await?;
+                self.len = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write any buffered data then commit the value
+    async fn commit<F, E, I>(mut self, store: &mut Store<F, I>) -> Result<(), Error<E>>
+    where
+        F: AsyncFlash<Error = E>,
+        E: Debug,
+        I: KeyIndex,
+    {
+        if self.len != self.blob.remaining() {
+            return Err(Error::LengthMismatch);
+        }
+
+        store
+            .write_chunks(&self.key, &mut self.blob, &self.buff[..self.len])
+            .await?;
+        store.commit_blob(&self.key, self.blob).await
+    }
+}